@@ -0,0 +1,87 @@
+#[macro_use]
+extern crate criterion;
+extern crate blockstack_lib;
+
+use blockstack_lib::vm::contexts::OwnedEnvironment;
+use blockstack_lib::vm::database::MemoryBackingStore;
+use blockstack_lib::vm::types::{QualifiedContractIdentifier, Value};
+
+use criterion::Criterion;
+
+const QUERIES: usize = 100;
+
+/// Evaluate the same handful of balance-style queries repeatedly, as a read-only
+///  RPC endpoint would, optionally consulting the parse cache.
+pub fn repeated_read_only_queries(parse_cache: bool) {
+    let mut store = MemoryBackingStore::new();
+    let mut owned_env = OwnedEnvironment::new(store.as_clarity_db());
+    let contract_identifier = QualifiedContractIdentifier::local("balances").unwrap();
+
+    owned_env
+        .initialize_contract(
+            contract_identifier.clone(),
+            "(define-map balances int int)
+             (map-set balances 1 100)
+             (map-set balances 2 200)
+             (define-read-only (get-balance (who int))
+               (default-to 0 (map-get? balances who)))",
+        )
+        .unwrap();
+
+    owned_env.begin();
+    let mut env = owned_env.get_exec_environment(None);
+    if parse_cache {
+        env.global_context.enable_parse_cache(16);
+    }
+
+    for i in 0..QUERIES {
+        let program = format!("(get-balance {})", i % 3);
+        env.eval_read_only(&contract_identifier, &program).unwrap();
+    }
+}
+
+/// Read `QUERIES` map entries, either one `get_map_entry` call at a time or in a single
+///  `get_map_entries` batch.
+pub fn map_entry_reads(batched: bool) {
+    let mut store = MemoryBackingStore::new();
+    let mut owned_env = OwnedEnvironment::new(store.as_clarity_db());
+    let contract_identifier = QualifiedContractIdentifier::local("balances").unwrap();
+
+    let mut contract = "(define-map balances int int)".to_string();
+    for i in 0..QUERIES {
+        contract.push_str(&format!("\n(map-set balances {} {})", i, i * 100));
+    }
+    owned_env
+        .initialize_contract(contract_identifier.clone(), &contract)
+        .unwrap();
+
+    let contract_name = contract_identifier.to_string();
+    let keys: Vec<_> = (0..QUERIES).map(|i| Value::Int(i as i128)).collect();
+
+    owned_env.begin();
+    let mut env = owned_env.get_exec_environment(None);
+    if batched {
+        env.get_map_entries(&contract_name, "balances", &keys)
+            .unwrap();
+    } else {
+        for key in keys.iter() {
+            env.get_map_entry(&contract_name, "balances", key).unwrap();
+        }
+    }
+}
+
+pub fn read_only_benchmark(c: &mut Criterion) {
+    c.bench_function("repeated_read_only_queries", |b| {
+        b.iter(|| repeated_read_only_queries(false))
+    });
+    c.bench_function("repeated_read_only_queries_parse_cache", |b| {
+        b.iter(|| repeated_read_only_queries(true))
+    });
+    c.bench_function("map_entry_reads", |b| b.iter(|| map_entry_reads(false)));
+    c.bench_function("map_entry_reads_batched", |b| {
+        b.iter(|| map_entry_reads(true))
+    });
+}
+
+criterion_group!(benches, read_only_benchmark);
+criterion_main!(benches);
@@ -441,8 +441,11 @@ impl StacksChainState {
     /// Apply a post-conditions check.
     /// Return true if they all pass.
     /// Return false if at least one fails.
-    fn check_transaction_postconditions(
-        post_conditions: &Vec<TransactionPostCondition>,
+    /// In `Deny` mode, every asset moved in `asset_map` must also be covered by a
+    /// post-condition. Public so that a transaction processor (or a wallet previewing a
+    /// transaction) can reconcile an asset map exactly as block processing would.
+    pub fn check_transaction_postconditions(
+        post_conditions: &[TransactionPostCondition],
         post_condition_mode: &TransactionPostConditionMode,
         origin_account: &StacksAccount,
         asset_map: &AssetMap,
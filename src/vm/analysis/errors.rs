@@ -60,6 +60,7 @@ pub enum CheckErrors {
     UnionTypeValueError(Vec<TypeSignature>, Value),
 
     ExpectedLiteral,
+    ExpectedLiteralArgument(usize),
     ExpectedOptionalType(TypeSignature),
     ExpectedResponseType(TypeSignature),
     ExpectedOptionalOrResponseType(TypeSignature),
@@ -302,6 +303,7 @@ impl DiagnosableError for CheckErrors {
     fn message(&self) -> String {
         match &self {
             CheckErrors::ExpectedLiteral => "expected a literal argument".into(),
+            CheckErrors::ExpectedLiteralArgument(index) => format!("expected a literal value for argument {}", index),
             CheckErrors::BadMatchOptionSyntax(source) =>
                 format!("match on a optional type uses the following syntax: (match input some-name if-some-expression if-none-expression). Caused by: {}",
                         source.message()),
@@ -89,6 +89,58 @@ impl ContractContext {
         }
     }
 
+    /// The context of an already-analyzed contract -- the inverse of `into_contract_analysis`.
+    pub fn from_contract_analysis(contract_analysis: &ContractAnalysis) -> ContractContext {
+        ContractContext {
+            variable_types: contract_analysis
+                .variable_types
+                .clone()
+                .into_iter()
+                .collect(),
+            private_function_types: contract_analysis
+                .private_function_types
+                .clone()
+                .into_iter()
+                .collect(),
+            public_function_types: contract_analysis
+                .public_function_types
+                .clone()
+                .into_iter()
+                .collect(),
+            read_only_function_types: contract_analysis
+                .read_only_function_types
+                .clone()
+                .into_iter()
+                .collect(),
+            map_types: contract_analysis.map_types.clone().into_iter().collect(),
+            persisted_variable_types: contract_analysis
+                .persisted_variable_types
+                .clone()
+                .into_iter()
+                .collect(),
+            fungible_tokens: contract_analysis
+                .fungible_tokens
+                .clone()
+                .into_iter()
+                .collect(),
+            non_fungible_tokens: contract_analysis
+                .non_fungible_tokens
+                .clone()
+                .into_iter()
+                .collect(),
+            traits: contract_analysis
+                .defined_traits
+                .clone()
+                .into_iter()
+                .collect(),
+            implemented_traits: contract_analysis
+                .implemented_traits
+                .clone()
+                .into_iter()
+                .collect(),
+        }
+    }
+
     pub fn check_name_used(&self, name: &str) -> CheckResult<()> {
         if self.variable_types.contains_key(name)
             || self.persisted_variable_types.contains_key(name)
@@ -335,6 +335,19 @@ impl<'a, 'b> TypeChecker<'a, 'b> {
         self.cost_track
     }
 
+    /// Type-checks `expression` as though it were a top-level statement of the contract
+    ///  described by `contract_analysis`, without re-analyzing the contract itself.
+    pub fn type_check_in_contract(
+        expression: &SymbolicExpression,
+        contract_analysis: &ContractAnalysis,
+        analysis_db: &mut AnalysisDatabase,
+        cost_track: LimitedCostTracker,
+    ) -> TypeResult {
+        let mut command = TypeChecker::new(analysis_db, cost_track);
+        command.contract_context = ContractContext::from_contract_analysis(contract_analysis);
+        command.type_check(expression, &TypingContext::new())
+    }
+
     pub fn track_return_type(&mut self, return_type: TypeSignature) -> CheckResult<()> {
         runtime_cost(
             ClarityCostFunction::AnalysisTypeCheck,
@@ -237,6 +237,21 @@ impl DefinedFunction {
     pub fn get_identifier(&self) -> FunctionIdentifier {
         self.identifier.clone()
     }
+
+    pub fn get_name(&self) -> &ClarityName {
+        &self.name
+    }
+
+    pub fn get_body(&self) -> &SymbolicExpression {
+        &self.body
+    }
+
+    /// A copy of this function, identified as defined in the contract `context_name`.
+    pub fn with_context_name(&self, context_name: &str) -> DefinedFunction {
+        let mut function = self.clone();
+        function.identifier = FunctionIdentifier::new_user_function(&self.name, context_name);
+        function
+    }
 }
 
 impl CallableType {
@@ -250,6 +265,21 @@ impl CallableType {
 }
 
 impl FunctionIdentifier {
+    pub fn is_native(&self) -> bool {
+        self.identifier.starts_with("_native_:")
+    }
+
+    /// The name of the function, without its `_native_` or contract qualifier.
+    pub fn get_function_name(&self) -> ClarityName {
+        let name: &str = match self.identifier.rfind(':') {
+            Some(index) => &self.identifier[index + 1..],
+            None => &self.identifier,
+        };
+        name.to_string()
+            .try_into()
+            .expect("ERROR: function identifier with an invalid function name")
+    }
+
     fn new_native_function(name: &str) -> FunctionIdentifier {
         let identifier = format!("_native_:{}", name);
         FunctionIdentifier {
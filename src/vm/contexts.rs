@@ -14,14 +14,20 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::fmt;
+use std::io::Read;
 use std::mem::replace;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use vm::analysis::type_checker::TypeChecker;
+use vm::analysis::ContractAnalysis;
 use vm::ast;
 use vm::ast::ContractAST;
-use vm::callables::{DefinedFunction, FunctionIdentifier};
+use vm::callables::{DefineType, DefinedFunction, FunctionIdentifier};
 use vm::contracts::Contract;
 use vm::costs::{
     cost_functions, runtime_cost, ClarityCostFunctionReference, CostErrors, CostTracker,
@@ -29,31 +35,41 @@ use vm::costs::{
 };
 use vm::database::{
     ClarityDatabase, DataMapMetadata, DataVariableMetadata, FungibleTokenMetadata,
-    NonFungibleTokenMetadata,
+    MemoryBackingStore, NonFungibleTokenMetadata,
+};
+use vm::errors::{
+    CheckErrors, Error, InterpreterError, InterpreterResult as Result, RuntimeErrorType,
 };
-use vm::errors::{CheckErrors, InterpreterError, InterpreterResult as Result, RuntimeErrorType};
 use vm::functions::handle_contract_call_special_cases;
-use vm::representations::{ClarityName, ContractName, SymbolicExpression};
+use vm::representations::{
+    depth_traverse, ClarityName, ContractName, SymbolicExpression, SymbolicExpressionType,
+};
 use vm::stx_transfer_consolidated;
 use vm::types::signatures::FunctionSignature;
 use vm::types::{
     AssetIdentifier, PrincipalData, QualifiedContractIdentifier, TraitIdentifier, TypeSignature,
     Value,
 };
-use vm::{eval, is_reserved};
+use vm::{eval, is_reserved, MAX_CALL_STACK_DEPTH};
 
 use chainstate::burn::{BlockHeaderHash, VRFSeed};
-use chainstate::stacks::db::StacksChainState;
+use chainstate::stacks::db::{StacksAccount, StacksChainState};
 use chainstate::stacks::events::*;
 use chainstate::stacks::Error as ChainstateError;
 use chainstate::stacks::StacksBlockId;
 use chainstate::stacks::StacksMicroblockHeader;
+use chainstate::stacks::{TransactionPostCondition, TransactionPostConditionMode};
 
 use serde::Serialize;
 use vm::costs::cost_functions::ClarityCostFunction;
 
 pub const MAX_CONTEXT_DEPTH: u16 = 256;
 
+// upper bound on the number of constants a single contract may define.
+//   real contracts define a few dozen at most. this is a consensus rule:
+//   a contract past the bound fails to deploy, so it must not vary by node.
+pub const MAX_CONTRACT_VARIABLES: usize = 4096;
+
 // TODO:
 //    hide the environment's instance variables.
 //     we don't want many of these changing after instantiation.
@@ -63,6 +79,8 @@ pub struct Environment<'a, 'b> {
     pub call_stack: &'a mut CallStack,
     pub sender: Option<Value>,
     pub caller: Option<Value>,
+    // the callers that preceded `caller`, outermost first.
+    callers: Vec<PrincipalData>,
 }
 
 pub struct OwnedEnvironment<'a> {
@@ -71,6 +89,57 @@ pub struct OwnedEnvironment<'a> {
     call_stack: CallStack,
 }
 
+/// Configuration for an `OwnedEnvironment`. Each field sets the `GlobalContext` field of
+///  the same name, except `cost_tracker`, which sets the cost budget. The default is a
+///  testnet environment without cost limits or strict asset checks.
+pub struct OwnedEnvironmentConfig {
+    pub mainnet: bool,
+    pub cost_tracker: LimitedCostTracker,
+    pub reject_zero_transfers: bool,
+    pub reject_duplicate_nft_transfers: bool,
+    pub max_asset_map_bytes: Option<usize>,
+    pub max_asset_map_entries: Option<usize>,
+    pub simulate_writes: bool,
+    pub strict_response: bool,
+    pub record_transfer_log: bool,
+}
+
+/// The outcome of `OwnedEnvironment::simulate_transaction`: what the transaction would
+///  have returned, moved, emitted, and cost had it been committed.
+#[derive(Debug)]
+pub struct SimulationResult {
+    pub value: Value,
+    pub asset_map: AssetMap,
+    pub events: Vec<StacksTransactionEvent>,
+    pub cost: ExecutionCost,
+    // whether `asset_map` satisfies the post-conditions passed to `simulate_transaction`.
+    pub post_conditions_passed: bool,
+}
+
+impl Default for OwnedEnvironmentConfig {
+    fn default() -> OwnedEnvironmentConfig {
+        OwnedEnvironmentConfig {
+            mainnet: false,
+            cost_tracker: LimitedCostTracker::new_free(),
+            reject_zero_transfers: false,
+            reject_duplicate_nft_transfers: false,
+            max_asset_map_bytes: None,
+            max_asset_map_entries: None,
+            simulate_writes: false,
+            strict_response: true,
+            record_transfer_log: false,
+        }
+    }
+}
+
+/// A transaction on an `OwnedEnvironment`, begun when the scope is created. Unless it
+///  is explicitly committed, it is rolled back when dropped, so an embedder's custom
+///  transaction body can return early (e.g., via `?`) without leaking the transaction.
+pub struct TransactionScope<'b, 'a: 'b> {
+    owned_env: &'b mut OwnedEnvironment<'a>,
+    finished: bool,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum AssetMapEntry {
     STX(u128),
@@ -79,6 +148,32 @@ pub enum AssetMapEntry {
     Asset(Vec<Value>),
 }
 
+/// What a `LedgerEntry` debits: an amount of STX or a fungible token, or a single NFT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerAmount {
+    Amount(u128),
+    Asset(Value),
+}
+
+/// One debit in the ledger projection of an `AssetMap`, produced by
+///  `AssetMap::to_ledger_entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry {
+    pub debit: PrincipalData,
+    pub asset: AssetIdentifier,
+    pub amount: LedgerAmount,
+}
+
+/// A single `add_*` call on an `AssetMap`, as recorded in its transfer log. `index` is the
+///  transfer's position in the log, so it increases in execution order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferEvent {
+    pub index: usize,
+    pub sender: PrincipalData,
+    pub asset: AssetIdentifier,
+    pub amount: LedgerAmount,
+}
+
 /**
 The AssetMap is used to track which assets have been transfered from whom
 during the execution of a transaction.
@@ -89,6 +184,22 @@ pub struct AssetMap {
     burn_map: HashMap<PrincipalData, u128>,
     token_map: HashMap<PrincipalData, HashMap<AssetIdentifier, u128>>,
     asset_map: HashMap<PrincipalData, HashMap<AssetIdentifier, Vec<Value>>>,
+    // when set, zero-amount token transfers are rejected rather than logged.
+    reject_zero_transfers: bool,
+    // when set, logging the same NFT transfer twice is rejected.
+    reject_duplicate_nft_transfers: bool,
+    // tags of the maps merged in via `commit_other_tagged`, per entry.
+    contributors: HashMap<PrincipalData, HashMap<AssetIdentifier, Vec<u32>>>,
+    // when set, token balances and NFT transfers beyond this many entries are rejected.
+    max_entries: Option<usize>,
+    // when set, every transfer logged, in the order it was logged.
+    transfer_log: Option<Vec<TransferEvent>>,
+    // block heights at which each entry's token and NFT transfers were logged.
+    #[cfg(feature = "developer-mode")]
+    transfer_heights: HashMap<PrincipalData, HashMap<AssetIdentifier, Vec<u32>>>,
+    // the largest running total reached by any principal's entry, per asset.
+    #[cfg(feature = "developer-mode")]
+    max_observed: HashMap<AssetIdentifier, u128>,
 }
 
 #[derive(Debug, Clone)]
@@ -96,6 +207,25 @@ pub struct EventBatch {
     pub events: Vec<StacksTransactionEvent>,
 }
 
+/// The outcome of committing a nested context. Only the outermost context hands its
+///  asset map, event batch, and printed values back to the caller -- every other commit
+///  merges them into the enclosing context.
+#[derive(Debug)]
+pub enum CommitOutcome {
+    TopLevel(AssetMap, EventBatch, Vec<Value>),
+    MergedUpward,
+}
+
+/// A context opened by `GlobalContext::begin_scope`. It can't be copied, and finishing
+///  the context consumes it, so a scope can only be committed or rolled back once. Each
+///  context gets a unique id, so a scope whose context was already finished some other way
+///  can't finish a later context opened at the same depth.
+#[must_use]
+#[derive(Debug, PartialEq)]
+pub struct ContextScope {
+    id: u64,
+}
+
 /** GlobalContext represents the outermost context for a single transaction's
      execution. It tracks an asset changes that occurred during the
      processing of the transaction, whether or not the current context is read_only,
@@ -109,6 +239,66 @@ pub struct GlobalContext<'a> {
     read_only: Vec<bool>,
     pub cost_track: LimitedCostTracker,
     pub mainnet: bool,
+    pub reject_zero_transfers: bool,
+    pub reject_duplicate_nft_transfers: bool,
+    // when set, committing a context whose merged asset map would exceed this many
+    //  (approximate) bytes fails with `AssetMapTooLarge`.
+    pub max_asset_map_bytes: Option<usize>,
+    // when set, every asset map is bounded to this many entries (see `AssetMap::bounded`).
+    pub max_asset_map_entries: Option<usize>,
+    // when set, writes attempted in a read-only context are skipped and recorded as
+    //  warnings rather than failing (see `check_writable`).
+    pub simulate_writes: bool,
+    // when unset, a public function returning something other than a response commits as
+    //  though it returned `(ok ...)` (see `handle_tx_result`). Only for legacy test contracts.
+    pub strict_response: bool,
+    // when set, every asset map records its transfers in execution order (see
+    //  `AssetMap::transfer_log`).
+    pub record_transfer_log: bool,
+    // cost refunded by operations that release resources (e.g., clearing storage),
+    //  nested like the asset maps.
+    cost_refunds: Vec<ExecutionCost>,
+    // the cost refunded by the last top-level transaction.
+    last_refund: ExecutionCost,
+    // set by an embedder (e.g., a watchdog thread) to cancel evaluation.
+    abort: Arc<AtomicBool>,
+    // values passed to `print`, nested like the asset maps.
+    print_sinks: Vec<Vec<Value>>,
+    // the id of each open context, nested like the asset maps, checked by `check_scope`.
+    context_ids: Vec<u64>,
+    // the id the next context opened will get.
+    next_context_id: u64,
+    // ids of the evaluated expressions, recorded only once enabled.
+    coverage: Option<HashSet<u64>>,
+    // invoked with (from, to, function) whenever a call crosses into another contract.
+    contract_call_observer: Option<
+        Box<dyn FnMut(&QualifiedContractIdentifier, &QualifiedContractIdentifier, &ClarityName)>,
+    >,
+    // parsed programs of `eval_read_only` and `eval_raw`, kept only once enabled.
+    parse_cache: Option<ParseCache>,
+    // whether the last top-level transaction wrote to the database or moved assets.
+    had_side_effects: bool,
+    // contracts kept in memory, consulted before the database when loading a contract.
+    pinned_contracts: HashMap<QualifiedContractIdentifier, Rc<Contract>>,
+    // writes attempted in a read-only context while simulating writes.
+    warnings: Vec<String>,
+    // results returned in place of executing the keyed (contract, function).
+    #[cfg(feature = "developer-mode")]
+    mocked_calls: HashMap<(QualifiedContractIdentifier, ClarityName), Value>,
+    // functions whose execution invokes `debug_callback` first.
+    #[cfg(feature = "developer-mode")]
+    breakpoints: HashSet<FunctionIdentifier>,
+    #[cfg(feature = "developer-mode")]
+    debug_callback: Option<Box<dyn FnMut(&Environment)>>,
+}
+
+/// A bounded, least-recently-used cache of parsed programs, keyed by the program text
+///  and the contract it's parsed in.
+struct ParseCache {
+    capacity: usize,
+    entries: HashMap<(QualifiedContractIdentifier, String), Vec<SymbolicExpression>>,
+    // least recently used first.
+    recency: VecDeque<(QualifiedContractIdentifier, String)>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -129,6 +319,17 @@ pub struct ContractContext {
     pub data_size: u64,
 }
 
+/// A call a function's body makes, as found by `ContractContext::call_graph`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CallTarget {
+    /// a function defined in the same contract.
+    Internal(ClarityName),
+    /// a `contract-call?` to a function of a named contract.
+    Contract(QualifiedContractIdentifier, ClarityName),
+    /// a `contract-call?` through a trait reference, whose contract is only known at runtime.
+    Dynamic(ClarityName),
+}
+
 pub struct LocalContext<'a> {
     pub function_context: Option<&'a LocalContext<'a>>,
     pub parent: Option<&'a LocalContext<'a>>,
@@ -139,14 +340,61 @@ pub struct LocalContext<'a> {
 
 pub struct CallStack {
     stack: Vec<FunctionIdentifier>,
+    // the contract active when each frame of `stack` was pushed.
+    contracts: Vec<ContractName>,
     set: HashSet<FunctionIdentifier>,
     apply_depth: usize,
+    // the deepest `depth()` reached since the last `reset_high_water_mark`.
+    high_water_mark: usize,
 }
 
 pub type StackTrace = Vec<FunctionIdentifier>;
 
 pub const TRANSIENT_CONTRACT_NAME: &str = "__transient";
 
+/// The version prefixed to `AssetMap::to_canonical_bytes`. Bump it whenever the format changes.
+pub const ASSET_MAP_FORMAT_VERSION: u16 = 1;
+
+// the kinds of entries in an asset map's canonical encoding.
+const CANONICAL_STX_ENTRY: u8 = 0;
+const CANONICAL_BURN_ENTRY: u8 = 1;
+const CANONICAL_TOKEN_ENTRY: u8 = 2;
+const CANONICAL_NFT_ENTRY: u8 = 3;
+
+/// Extract the literal values from a transaction's arguments. Fails with the index of
+///  the first argument that isn't a literal value, so that API layers can validate
+///  client-supplied arguments before attempting execution.
+pub fn extract_atom_values(args: &[SymbolicExpression]) -> Result<Vec<Value>> {
+    let mut values = Vec::with_capacity(args.len());
+    for (index, arg) in args.iter().enumerate() {
+        let value = arg
+            .match_atom_value()
+            .ok_or(CheckErrors::ExpectedLiteralArgument(index))?;
+        values.push(value.clone());
+    }
+    Ok(values)
+}
+
+impl AssetMapEntry {
+    /// The amount moved, for STX, STX burn, and fungible token entries.
+    pub fn as_amount(&self) -> Option<u128> {
+        match self {
+            AssetMapEntry::STX(amount)
+            | AssetMapEntry::Burn(amount)
+            | AssetMapEntry::Token(amount) => Some(*amount),
+            AssetMapEntry::Asset(_) => None,
+        }
+    }
+
+    /// The number of non-fungible tokens moved, for NFT entries.
+    pub fn nft_count(&self) -> Option<usize> {
+        match self {
+            AssetMapEntry::Asset(transfers) => Some(transfers.len()),
+            _ => None,
+        }
+    }
+}
+
 impl AssetMap {
     pub fn new() -> AssetMap {
         AssetMap {
@@ -154,9 +402,125 @@ impl AssetMap {
             burn_map: HashMap::new(),
             token_map: HashMap::new(),
             asset_map: HashMap::new(),
+            reject_zero_transfers: false,
+            reject_duplicate_nft_transfers: false,
+            contributors: HashMap::new(),
+            max_entries: None,
+            transfer_log: None,
+            #[cfg(feature = "developer-mode")]
+            transfer_heights: HashMap::new(),
+            #[cfg(feature = "developer-mode")]
+            max_observed: HashMap::new(),
+        }
+    }
+
+    /// An asset map holding at most `max_entries` entries, counting each (principal, token)
+    ///  balance and each logged NFT transfer as one entry. Token transfers and checked NFT
+    ///  transfers (see `add_asset_transfer_checked`) that would exceed the bound fail with
+    ///  `RuntimeErrorType::AssetMapFull`. STX transfers and burns are not counted.
+    pub fn bounded(max_entries: usize) -> AssetMap {
+        let mut asset_map = AssetMap::new();
+        asset_map.max_entries = Some(max_entries);
+        asset_map
+    }
+
+    fn entry_count(&self) -> usize {
+        let token_entries: usize = self.token_map.values().map(|assets| assets.len()).sum();
+        let nft_entries: usize = self
+            .asset_map
+            .values()
+            .flat_map(|assets| assets.values())
+            .map(|transfers| transfers.len())
+            .sum();
+        token_entries + nft_entries
+    }
+
+    fn check_capacity(&self, new_entries: usize) -> Result<()> {
+        match self.max_entries {
+            Some(max_entries)
+                if new_entries > 0 && self.entry_count() + new_entries > max_entries =>
+            {
+                Err(RuntimeErrorType::AssetMapFull.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn has_token_entry(&self, principal: &PrincipalData, asset: &AssetIdentifier) -> bool {
+        match self.token_map.get(principal) {
+            Some(principal_map) => principal_map.contains_key(asset),
+            None => false,
+        }
+    }
+
+    /// Opt-in strict mode: reject zero-amount token transfers with
+    ///  `RuntimeErrorType::ZeroTokenTransfer` instead of recording a no-op entry.
+    pub fn set_reject_zero_transfers(&mut self, reject: bool) {
+        self.reject_zero_transfers = reject;
+    }
+
+    /// Opt-in strict mode: `add_asset_transfer_checked` rejects logging the same NFT value
+    ///  twice for a (principal, asset) pair with `RuntimeErrorType::DuplicateNftTransfer`.
+    pub fn set_reject_duplicate_nft_transfers(&mut self, reject: bool) {
+        self.reject_duplicate_nft_transfers = reject;
+    }
+
+    /// Opt-in: record every transfer in a log, in execution order, alongside the
+    ///  per-principal totals (see `transfer_log`). Disabling the log discards it.
+    pub fn set_record_transfer_log(&mut self, record: bool) {
+        self.transfer_log = match self.transfer_log.take() {
+            Some(log) if record => Some(log),
+            _ if record => Some(Vec::new()),
+            _ => None,
+        };
+    }
+
+    /// The transfers logged so far, in the order they were logged, or `None` if the
+    ///  transfer log isn't being recorded. Transfers committed from a nested context's map
+    ///  appear at the point that context committed.
+    pub fn transfer_log(&self) -> Option<&[TransferEvent]> {
+        self.transfer_log.as_ref().map(|log| log.as_slice())
+    }
+
+    fn log_transfer(
+        &mut self,
+        sender: &PrincipalData,
+        asset: AssetIdentifier,
+        amount: LedgerAmount,
+    ) {
+        if let Some(ref mut log) = self.transfer_log {
+            log.push(TransferEvent {
+                index: log.len(),
+                sender: sender.clone(),
+                asset,
+                amount,
+            });
+        }
+    }
+
+    fn has_nft_transfer(
+        &self,
+        principal: &PrincipalData,
+        asset: &AssetIdentifier,
+        transfered: &Value,
+    ) -> bool {
+        match self.asset_map.get(principal) {
+            Some(principal_map) => match principal_map.get(asset) {
+                Some(transfers) => transfers.contains(transfered),
+                None => false,
+            },
+            None => false,
         }
     }
 
+    /// True if no assets were moved or burned.
+    pub fn is_empty(&self) -> bool {
+        self.stx_map.is_empty()
+            && self.burn_map.is_empty()
+            && self.token_map.is_empty()
+            && self.asset_map.is_empty()
+    }
+
     // This will get the next amount for a (principal, stx) entry in the stx table.
     fn get_next_stx_amount(&self, principal: &PrincipalData, amount: u128) -> Result<u128> {
         let current_amount = self.stx_map.get(principal).unwrap_or(&0);
@@ -192,14 +556,28 @@ impl AssetMap {
 
     pub fn add_stx_transfer(&mut self, principal: &PrincipalData, amount: u128) -> Result<()> {
         let next_amount = self.get_next_stx_amount(principal, amount)?;
+        #[cfg(feature = "developer-mode")]
+        self.observe_amount(&AssetIdentifier::STX(), next_amount);
         self.stx_map.insert(principal.clone(), next_amount);
+        self.log_transfer(
+            principal,
+            AssetIdentifier::STX(),
+            LedgerAmount::Amount(amount),
+        );
 
         Ok(())
     }
 
     pub fn add_stx_burn(&mut self, principal: &PrincipalData, amount: u128) -> Result<()> {
         let next_amount = self.get_next_stx_burn_amount(principal, amount)?;
+        #[cfg(feature = "developer-mode")]
+        self.observe_amount(&AssetIdentifier::STX_burned(), next_amount);
         self.burn_map.insert(principal.clone(), next_amount);
+        self.log_transfer(
+            principal,
+            AssetIdentifier::STX_burned(),
+            LedgerAmount::Amount(amount),
+        );
 
         Ok(())
     }
@@ -210,6 +588,14 @@ impl AssetMap {
         asset: AssetIdentifier,
         transfered: Value,
     ) {
+        if self.transfer_log.is_some() {
+            self.log_transfer(
+                principal,
+                asset.clone(),
+                LedgerAmount::Asset(transfered.clone()),
+            );
+        }
+
         if !self.asset_map.contains_key(principal) {
             self.asset_map.insert(principal.clone(), HashMap::new());
         }
@@ -223,18 +609,55 @@ impl AssetMap {
         }
     }
 
+    /// Like `add_asset_transfer`, but enforces the opt-in checks: logging an NFT value
+    ///  already logged for the (principal, asset) pair fails with `DuplicateNftTransfer`
+    ///  once `set_reject_duplicate_nft_transfers` is set, and a full bounded map fails with
+    ///  `AssetMapFull`.
+    pub fn add_asset_transfer_checked(
+        &mut self,
+        principal: &PrincipalData,
+        asset: AssetIdentifier,
+        transfered: Value,
+    ) -> Result<()> {
+        if self.reject_duplicate_nft_transfers
+            && self.has_nft_transfer(principal, &asset, &transfered)
+        {
+            return Err(RuntimeErrorType::DuplicateNftTransfer.into());
+        }
+
+        self.check_capacity(1)?;
+
+        self.add_asset_transfer(principal, asset, transfered);
+        Ok(())
+    }
+
     pub fn add_token_transfer(
         &mut self,
         principal: &PrincipalData,
         asset: AssetIdentifier,
         amount: u128,
     ) -> Result<()> {
+        if self.reject_zero_transfers && amount == 0 {
+            return Err(RuntimeErrorType::ZeroTokenTransfer.into());
+        }
+
         let next_amount = self.get_next_amount(principal, &asset, amount)?;
 
+        if !self.has_token_entry(principal, &asset) {
+            self.check_capacity(1)?;
+        }
+
         if !self.token_map.contains_key(principal) {
             self.token_map.insert(principal.clone(), HashMap::new());
         }
 
+        #[cfg(feature = "developer-mode")]
+        self.observe_amount(&asset, next_amount);
+
+        if self.transfer_log.is_some() {
+            self.log_transfer(principal, asset.clone(), LedgerAmount::Amount(amount));
+        }
+
         let principal_map = self.token_map.get_mut(principal).unwrap(); // should always exist, because of checked insert above.
 
         principal_map.insert(asset, next_amount);
@@ -266,6 +689,26 @@ impl AssetMap {
             stx_burn_to_add.push((principal.clone(), next_amount));
         }
 
+        if self.reject_duplicate_nft_transfers {
+            for (principal, principal_map) in other.asset_map.iter() {
+                for (asset, transfers) in principal_map.iter() {
+                    for transfered in transfers.iter() {
+                        if self.has_nft_transfer(principal, asset, transfered) {
+                            return Err(RuntimeErrorType::DuplicateNftTransfer.into());
+                        }
+                    }
+                }
+            }
+        }
+
+        let new_token_entries = to_add
+            .iter()
+            .filter(|(principal, asset, _)| !self.has_token_entry(principal, asset))
+            .count();
+        // other's token map was drained above, so this only counts its NFT transfers.
+        let new_nft_entries = other.entry_count();
+        self.check_capacity(new_token_entries + new_nft_entries)?;
+
         // After this point, this function will not fail.
         for (principal, mut principal_map) in other.asset_map.drain() {
             for (asset, mut transfers) in principal_map.drain() {
@@ -284,14 +727,20 @@ impl AssetMap {
         }
 
         for (principal, stx_amount) in stx_to_add.drain(..) {
+            #[cfg(feature = "developer-mode")]
+            self.observe_amount(&AssetIdentifier::STX(), stx_amount);
             self.stx_map.insert(principal, stx_amount);
         }
 
         for (principal, stx_burn_amount) in stx_burn_to_add.drain(..) {
+            #[cfg(feature = "developer-mode")]
+            self.observe_amount(&AssetIdentifier::STX_burned(), stx_burn_amount);
             self.burn_map.insert(principal, stx_burn_amount);
         }
 
         for (principal, asset, amount) in to_add.drain(..) {
+            #[cfg(feature = "developer-mode")]
+            self.observe_amount(&asset, amount);
             if !self.token_map.contains_key(&principal) {
                 self.token_map.insert(principal.clone(), HashMap::new());
             }
@@ -300,9 +749,437 @@ impl AssetMap {
             principal_map.insert(asset, amount);
         }
 
+        #[cfg(feature = "developer-mode")]
+        for (principal, mut principal_map) in other.transfer_heights.drain() {
+            for (asset, mut heights) in principal_map.drain() {
+                self.transfer_heights
+                    .entry(principal.clone())
+                    .or_insert_with(HashMap::new)
+                    .entry(asset)
+                    .or_insert_with(Vec::new)
+                    .append(&mut heights);
+            }
+        }
+
+        if let (Some(log), Some(other_log)) = (self.transfer_log.as_mut(), other.transfer_log) {
+            for mut transfer in other_log.into_iter() {
+                transfer.index = log.len();
+                log.push(transfer);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A new map combining `self` and `other`, as `commit_other` would, leaving both
+    ///  untouched. Both maps are copied, so accumulating many maps this way allocates far
+    ///  more than folding them into a single map with `commit_other`; prefer that unless
+    ///  the intermediate results are needed. The result keeps `self`'s strict-mode settings.
+    pub fn merged(&self, other: &AssetMap) -> Result<AssetMap> {
+        let mut merged = self.clone();
+        merged.commit_other(other.clone())?;
+        Ok(merged)
+    }
+
+    /// What was logged into this map since `earlier`, a snapshot (e.g., a clone) of it:
+    ///  each STX, burn, and token amount less the amount in `earlier`, and the NFT
+    ///  transfers not already in `earlier`. Entries that did not change are omitted.
+    ///  Amounts are never negative: an entry that shrank (i.e., `earlier` was not a
+    ///  snapshot of this map) is omitted as well.
+    pub fn delta_since(&self, earlier: &AssetMap) -> AssetMap {
+        let mut delta = AssetMap::new();
+
+        for (principal, amount) in self.stx_map.iter() {
+            let prior = earlier.get_stx(principal).unwrap_or(0);
+            if *amount > prior {
+                delta.stx_map.insert(principal.clone(), amount - prior);
+            }
+        }
+
+        for (principal, amount) in self.burn_map.iter() {
+            let prior = earlier.get_stx_burned(principal).unwrap_or(0);
+            if *amount > prior {
+                delta.burn_map.insert(principal.clone(), amount - prior);
+            }
+        }
+
+        for (principal, principal_map) in self.token_map.iter() {
+            for (asset, amount) in principal_map.iter() {
+                let prior = earlier.get_fungible_tokens(principal, asset).unwrap_or(0);
+                if *amount > prior {
+                    delta
+                        .token_map
+                        .entry(principal.clone())
+                        .or_insert_with(HashMap::new)
+                        .insert(asset.clone(), amount - prior);
+                }
+            }
+        }
+
+        for (principal, principal_map) in self.asset_map.iter() {
+            for (asset, transfers) in principal_map.iter() {
+                let mut new_transfers = transfers.clone();
+                if let Some(prior) = earlier.get_nonfungible_tokens(principal, asset) {
+                    for transfered in prior.iter() {
+                        if let Some(index) = new_transfers.iter().position(|v| v == transfered) {
+                            new_transfers.remove(index);
+                        }
+                    }
+                }
+                if !new_transfers.is_empty() {
+                    delta
+                        .asset_map
+                        .entry(principal.clone())
+                        .or_insert_with(HashMap::new)
+                        .insert(asset.clone(), new_transfers);
+                }
+            }
+        }
+
+        delta
+    }
+
+    /// Like `commit_other`, but records `tag` as a contributor to every entry `other`
+    ///  touches, so that an aggregate can be traced back to the maps that produced it.
+    ///  STX transfers and burns are recorded under `AssetIdentifier::STX()` and
+    ///  `AssetIdentifier::STX_burned()`, as in `to_table`.
+    pub fn commit_other_tagged(&mut self, other: AssetMap, tag: u32) -> Result<()> {
+        let mut touched = Vec::new();
+        for (principal, principal_map) in other.token_map.iter() {
+            for asset in principal_map.keys() {
+                touched.push((principal.clone(), asset.clone()));
+            }
+        }
+        for (principal, principal_map) in other.asset_map.iter() {
+            for asset in principal_map.keys() {
+                touched.push((principal.clone(), asset.clone()));
+            }
+        }
+        for principal in other.stx_map.keys() {
+            touched.push((principal.clone(), AssetIdentifier::STX()));
+        }
+        for principal in other.burn_map.keys() {
+            touched.push((principal.clone(), AssetIdentifier::STX_burned()));
+        }
+
+        self.commit_other(other)?;
+
+        for (principal, asset) in touched.drain(..) {
+            let tags = self
+                .contributors
+                .entry(principal)
+                .or_insert_with(HashMap::new)
+                .entry(asset)
+                .or_insert_with(Vec::new);
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
         Ok(())
     }
 
+    pub fn contributors(&self, principal: &PrincipalData, asset: &AssetIdentifier) -> &[u32] {
+        match self.contributors.get(principal) {
+            Some(assets) => match assets.get(asset) {
+                Some(tags) => tags,
+                None => &[],
+            },
+            None => &[],
+        }
+    }
+
+    #[cfg(feature = "developer-mode")]
+    fn observe_amount(&mut self, asset: &AssetIdentifier, amount: u128) {
+        let observed = self.max_observed.entry(asset.clone()).or_insert(0);
+        *observed = (*observed).max(amount);
+    }
+
+    /// The largest running total any principal's entry for `asset` reached in this map,
+    ///  or 0 if the asset was never moved. STX transfers and burns are recorded under
+    ///  `AssetIdentifier::STX()` and `AssetIdentifier::STX_burned()`. Totals that approach
+    ///  `u128::max_value()` flag token accounting at risk of `ArithmeticOverflow`.
+    #[cfg(feature = "developer-mode")]
+    pub fn max_observed(&self, asset: &AssetIdentifier) -> u128 {
+        self.max_observed.get(asset).cloned().unwrap_or(0)
+    }
+
+    #[cfg(feature = "developer-mode")]
+    fn record_transfer_height(
+        &mut self,
+        principal: &PrincipalData,
+        asset: AssetIdentifier,
+        height: u32,
+    ) {
+        self.transfer_heights
+            .entry(principal.clone())
+            .or_insert_with(HashMap::new)
+            .entry(asset)
+            .or_insert_with(Vec::new)
+            .push(height);
+    }
+
+    /// The block heights at which `principal`'s transfers of `asset` were logged, one per
+    ///  token or NFT transfer, in the order they were logged. Heights survive nested
+    ///  commits, so a transaction's asset map carries every transfer its callees made.
+    #[cfg(feature = "developer-mode")]
+    pub fn transfer_heights(&self, principal: &PrincipalData, asset: &AssetIdentifier) -> &[u32] {
+        match self.transfer_heights.get(principal) {
+            Some(assets) => match assets.get(asset) {
+                Some(heights) => heights,
+                None => &[],
+            },
+            None => &[],
+        }
+    }
+
+    /// A human-readable diff against an `expected` map, one line per differing entry,
+    ///  sorted by principal and then asset:
+    ///    `- principal asset: amount` for entries only in `expected`,
+    ///    `+ principal asset: amount` for entries only in `self`, and
+    ///    `~ principal asset: expected amount, got amount` for entries that differ.
+    ///  Returns an empty string if the maps are equal.
+    pub fn diff(&self, expected: &AssetMap) -> String {
+        let actual_entries = self.canonical_entries();
+        let expected_entries = expected.canonical_entries();
+
+        let keys: BTreeSet<_> = actual_entries
+            .keys()
+            .chain(expected_entries.keys())
+            .collect();
+
+        let mut diff = String::new();
+        for key in keys.into_iter() {
+            let (ref principal, ref asset) = *key;
+            match (actual_entries.get(key), expected_entries.get(key)) {
+                (None, Some(expected)) => {
+                    diff.push_str(&format!("- {} {}: {}\n", principal, asset, expected))
+                }
+                (Some(actual), None) => {
+                    diff.push_str(&format!("+ {} {}: {}\n", principal, asset, actual))
+                }
+                (Some(actual), Some(expected)) if actual != expected => diff.push_str(&format!(
+                    "~ {} {}: expected {}, got {}\n",
+                    principal, asset, expected, actual
+                )),
+                _ => {}
+            }
+        }
+        diff
+    }
+
+    /// A deterministic encoding of this map's STX transfers and burns, token transfers, and
+    ///  NFT transfers, for exchanging asset maps between nodes: the big-endian
+    ///  `ASSET_MAP_FORMAT_VERSION`, the big-endian entry count, then the entries in
+    ///  byte order. Each entry is a kind byte and the consensus-serialized principal,
+    ///  followed by the asset (contract principal, then length-prefixed name) for token and
+    ///  NFT entries, and the amount (as a `uint`) or the count and values of the NFT
+    ///  transfers. The opt-in strict modes and entry bound are not encoded.
+    pub fn to_canonical_bytes(&self) -> Vec<u8> {
+        let mut entries = Vec::new();
+        for (principal, stx_amount) in self.stx_map.iter() {
+            let mut entry = vec![CANONICAL_STX_ENTRY];
+            write_canonical_value(&mut entry, &Value::from(principal.clone()));
+            write_canonical_value(&mut entry, &Value::UInt(*stx_amount));
+            entries.push(entry);
+        }
+        for (principal, stx_burn_amount) in self.burn_map.iter() {
+            let mut entry = vec![CANONICAL_BURN_ENTRY];
+            write_canonical_value(&mut entry, &Value::from(principal.clone()));
+            write_canonical_value(&mut entry, &Value::UInt(*stx_burn_amount));
+            entries.push(entry);
+        }
+        for (principal, principal_map) in self.token_map.iter() {
+            for (asset, amount) in principal_map.iter() {
+                let mut entry = vec![CANONICAL_TOKEN_ENTRY];
+                write_canonical_value(&mut entry, &Value::from(principal.clone()));
+                write_canonical_asset(&mut entry, asset);
+                write_canonical_value(&mut entry, &Value::UInt(*amount));
+                entries.push(entry);
+            }
+        }
+        for (principal, principal_map) in self.asset_map.iter() {
+            for (asset, transfers) in principal_map.iter() {
+                let mut entry = vec![CANONICAL_NFT_ENTRY];
+                write_canonical_value(&mut entry, &Value::from(principal.clone()));
+                write_canonical_asset(&mut entry, asset);
+                entry.extend_from_slice(&(transfers.len() as u32).to_be_bytes());
+                for transfered in transfers.iter() {
+                    write_canonical_value(&mut entry, transfered);
+                }
+                entries.push(entry);
+            }
+        }
+        entries.sort();
+
+        let mut bytes = ASSET_MAP_FORMAT_VERSION.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for entry in entries.iter() {
+            bytes.extend_from_slice(entry);
+        }
+        bytes
+    }
+
+    /// Decode an asset map produced by `to_canonical_bytes`. Fails with
+    ///  `InterpreterError::UnsupportedAssetMapVersion` if it was encoded with another
+    ///  format version, and `FailedToConstructAssetTable` if it is malformed.
+    pub fn from_canonical_bytes(bytes: &[u8]) -> Result<AssetMap> {
+        let mut reader = bytes;
+        let mut version = [0u8; 2];
+        read_canonical_bytes(&mut reader, &mut version)?;
+        let version = u16::from_be_bytes(version);
+        if version != ASSET_MAP_FORMAT_VERSION {
+            return Err(InterpreterError::UnsupportedAssetMapVersion(version).into());
+        }
+
+        let mut asset_map = AssetMap::new();
+        let entry_count = read_canonical_u32(&mut reader)?;
+        for _ in 0..entry_count {
+            let mut kind = [0u8; 1];
+            read_canonical_bytes(&mut reader, &mut kind)?;
+            let principal = match read_canonical_value(&mut reader)? {
+                Value::Principal(principal) => principal,
+                _ => return Err(InterpreterError::FailedToConstructAssetTable.into()),
+            };
+            match kind[0] {
+                CANONICAL_STX_ENTRY => {
+                    let amount = read_canonical_amount(&mut reader)?;
+                    asset_map.add_stx_transfer(&principal, amount)?;
+                }
+                CANONICAL_BURN_ENTRY => {
+                    let amount = read_canonical_amount(&mut reader)?;
+                    asset_map.add_stx_burn(&principal, amount)?;
+                }
+                CANONICAL_TOKEN_ENTRY => {
+                    let asset = read_canonical_asset(&mut reader)?;
+                    let amount = read_canonical_amount(&mut reader)?;
+                    asset_map.add_token_transfer(&principal, asset, amount)?;
+                }
+                CANONICAL_NFT_ENTRY => {
+                    let asset = read_canonical_asset(&mut reader)?;
+                    for _ in 0..read_canonical_u32(&mut reader)? {
+                        let transfered = read_canonical_value(&mut reader)?;
+                        asset_map.add_asset_transfer(&principal, asset.clone(), transfered);
+                    }
+                }
+                _ => return Err(InterpreterError::FailedToConstructAssetTable.into()),
+            }
+        }
+
+        if !reader.is_empty() {
+            return Err(InterpreterError::FailedToConstructAssetTable.into());
+        }
+        Ok(asset_map)
+    }
+
+    /// The token balances, as (principal, asset, amount), sorted by principal and then
+    ///  asset in the same order as `diff`, so that receipts and golden tests built from
+    ///  them are reproducible.
+    pub fn iter_tokens_sorted<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (&'a PrincipalData, &'a AssetIdentifier, u128)> + 'a {
+        let mut tokens: Vec<_> = self
+            .token_map
+            .iter()
+            .flat_map(|(principal, principal_map)| {
+                principal_map
+                    .iter()
+                    .map(move |(asset, amount)| (principal, asset, *amount))
+            })
+            .collect();
+        tokens.sort_by_cached_key(|(principal, asset, _)| canonical_key(principal, asset));
+        tokens.into_iter()
+    }
+
+    /// The NFT transfers, as (principal, asset, values), sorted like `iter_tokens_sorted`.
+    ///  The values are in the order they were transferred.
+    pub fn iter_assets_sorted<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (&'a PrincipalData, &'a AssetIdentifier, &'a [Value])> + 'a {
+        let mut assets: Vec<_> = self
+            .asset_map
+            .iter()
+            .flat_map(|(principal, principal_map)| {
+                principal_map
+                    .iter()
+                    .map(move |(asset, transfers)| (principal, asset, transfers.as_slice()))
+            })
+            .collect();
+        assets.sort_by_cached_key(|(principal, asset, _)| canonical_key(principal, asset));
+        assets.into_iter()
+    }
+
+    /// The map as ledger entries: one debit per STX, burn, and token balance, and one per
+    ///  NFT transferred, sorted like `iter_tokens_sorted`. The map only records senders,
+    ///  so these are the debit side alone -- the matching credits (and whether a debit was
+    ///  a burn rather than a transfer to someone) have to come from the event log.
+    pub fn to_ledger_entries(&self) -> Vec<LedgerEntry> {
+        let mut entries = Vec::new();
+        for (principal, amount) in self.stx_map.iter() {
+            entries.push(LedgerEntry {
+                debit: principal.clone(),
+                asset: AssetIdentifier::STX(),
+                amount: LedgerAmount::Amount(*amount),
+            });
+        }
+        for (principal, amount) in self.burn_map.iter() {
+            entries.push(LedgerEntry {
+                debit: principal.clone(),
+                asset: AssetIdentifier::STX_burned(),
+                amount: LedgerAmount::Amount(*amount),
+            });
+        }
+        for (principal, asset, amount) in self.iter_tokens_sorted() {
+            entries.push(LedgerEntry {
+                debit: principal.clone(),
+                asset: asset.clone(),
+                amount: LedgerAmount::Amount(amount),
+            });
+        }
+        for (principal, asset, transfers) in self.iter_assets_sorted() {
+            for transfer in transfers.iter() {
+                entries.push(LedgerEntry {
+                    debit: principal.clone(),
+                    asset: asset.clone(),
+                    amount: LedgerAmount::Asset(transfer.clone()),
+                });
+            }
+        }
+        // the sort is stable, so each principal's NFTs stay in the order they were transferred.
+        entries.sort_by_cached_key(|entry| canonical_key(&entry.debit, &entry.asset));
+        entries
+    }
+
+    fn canonical_entries(&self) -> BTreeMap<(String, String), String> {
+        let mut entries = BTreeMap::new();
+        for (principal, principal_map) in self.token_map.iter() {
+            for (asset, amount) in principal_map.iter() {
+                entries.insert(canonical_key(principal, asset), amount.to_string());
+            }
+        }
+        for (principal, principal_map) in self.asset_map.iter() {
+            for (asset, transfers) in principal_map.iter() {
+                let transfers: Vec<String> = transfers.iter().map(|t| t.to_string()).collect();
+                entries.insert(
+                    canonical_key(principal, asset),
+                    format!("[{}]", transfers.join(", ")),
+                );
+            }
+        }
+        for (principal, stx_amount) in self.stx_map.iter() {
+            entries.insert(
+                canonical_key(principal, &AssetIdentifier::STX()),
+                stx_amount.to_string(),
+            );
+        }
+        for (principal, stx_burn_amount) in self.burn_map.iter() {
+            entries.insert(
+                canonical_key(principal, &AssetIdentifier::STX_burned()),
+                stx_burn_amount.to_string(),
+            );
+        }
+        entries
+    }
+
     pub fn to_table(mut self) -> HashMap<PrincipalData, HashMap<AssetIdentifier, AssetMapEntry>> {
         let mut map = HashMap::new();
         for (principal, mut principal_map) in self.token_map.drain() {
@@ -406,19 +1283,232 @@ impl AssetMap {
             None => None,
         }
     }
-}
 
-impl fmt::Display for AssetMap {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[")?;
-        for (principal, principal_map) in self.token_map.iter() {
-            for (asset, amount) in principal_map.iter() {
-                write!(f, "{} spent {} {}\n", principal, amount, asset)?;
+    /// The number of `asset_identifier` NFTs logged as transferred by `principal` (i.e., the
+    ///  length of `get_nonfungible_tokens`), or 0 if there were none.
+    pub fn nft_count_for(
+        &self,
+        principal: &PrincipalData,
+        asset_identifier: &AssetIdentifier,
+    ) -> usize {
+        self.get_nonfungible_tokens(principal, asset_identifier)
+            .map(|values| values.len())
+            .unwrap_or(0)
+    }
+
+    /// Returns every principal that transferred `token` of the given NFT asset
+    ///  during this transaction.
+    pub fn senders_of_nft(&self, asset: &AssetIdentifier, token: &Value) -> Vec<&PrincipalData> {
+        self.asset_map
+            .iter()
+            .filter(|(_, assets)| match assets.get(asset) {
+                Some(transfers) => transfers.contains(token),
+                None => false,
+            })
+            .map(|(principal, _)| principal)
+            .collect()
+    }
+
+    /// A deterministic estimate of the bytes this map holds: each principal and asset
+    ///  identifier entry, plus 16 bytes per amount and the size of each NFT value.
+    pub fn approximate_bytes(&self) -> usize {
+        fn principal_bytes(principal: &PrincipalData) -> usize {
+            match principal {
+                PrincipalData::Standard(_) => 21,
+                PrincipalData::Contract(contract_identifier) => {
+                    21 + contract_identifier.name.as_str().len()
+                }
+            }
+        }
+        fn asset_bytes(asset: &AssetIdentifier) -> usize {
+            21 + asset.contract_identifier.name.as_str().len() + asset.asset_name.as_str().len()
+        }
+
+        let mut bytes = 0;
+        for principal in self.stx_map.keys().chain(self.burn_map.keys()) {
+            bytes += principal_bytes(principal) + 16;
+        }
+        for (principal, principal_map) in self.token_map.iter() {
+            bytes += principal_bytes(principal);
+            for asset in principal_map.keys() {
+                bytes += asset_bytes(asset) + 16;
+            }
+        }
+        for (principal, principal_map) in self.asset_map.iter() {
+            bytes += principal_bytes(principal);
+            for (asset, transfers) in principal_map.iter() {
+                bytes += asset_bytes(asset);
+                for transfered in transfers.iter() {
+                    bytes += transfered.size() as usize;
+                }
+            }
+        }
+        bytes
+    }
+
+    /// The distinct fungible and non-fungible assets moved by any principal. STX
+    ///  transfers and burns are not included.
+    pub fn asset_identifiers(&self) -> HashSet<&AssetIdentifier> {
+        let mut asset_identifiers = HashSet::new();
+        for principal_map in self.token_map.values() {
+            asset_identifiers.extend(principal_map.keys());
+        }
+        for principal_map in self.asset_map.values() {
+            asset_identifiers.extend(principal_map.keys());
+        }
+        asset_identifiers
+    }
+}
+
+// The key that orders asset map entries canonically: by principal, then asset, as strings.
+fn canonical_key(principal: &PrincipalData, asset: &AssetIdentifier) -> (String, String) {
+    (principal.to_string(), asset.to_string())
+}
+
+// Reads `principal`'s available STX balance, followed by its balance of each of `tokens`.
+fn fetch_balances(
+    database: &mut ClarityDatabase,
+    principal: &PrincipalData,
+    tokens: &[AssetIdentifier],
+) -> Result<Vec<u128>> {
+    let mut balances = Vec::with_capacity(tokens.len() + 1);
+    balances.push(
+        database
+            .get_stx_balance_snapshot(principal)
+            .get_available_balance(),
+    );
+    for token in tokens.iter() {
+        balances.push(database.get_ft_balance(
+            &token.contract_identifier,
+            &token.asset_name,
+            principal,
+            None,
+        )?);
+    }
+    Ok(balances)
+}
+
+fn balance_delta(before: u128, after: u128) -> Result<i128> {
+    let delta = if after >= before {
+        (after - before).try_into().ok()
+    } else {
+        (before - after).try_into().ok().map(|delta: i128| -delta)
+    };
+    delta.ok_or(RuntimeErrorType::ArithmeticOverflow.into())
+}
+
+// Reads the entries of `map_name` at each of `keys`, or all `None` if the map doesn't exist.
+fn fetch_map_entries(
+    database: &mut ClarityDatabase,
+    contract_identifier: &QualifiedContractIdentifier,
+    map_name: &str,
+    keys: &[Value],
+) -> Result<Vec<Option<Value>>> {
+    let descriptor = match database.load_map(contract_identifier, map_name) {
+        Ok(descriptor) => descriptor,
+        Err(Error::Unchecked(CheckErrors::NoSuchMap(_))) => return Ok(vec![None; keys.len()]),
+        Err(e) => return Err(e),
+    };
+
+    keys.iter()
+        .map(|key| {
+            database
+                .fetch_entry(contract_identifier, map_name, key, &descriptor)
+                .map(|entry| entry.expect_optional())
+        })
+        .collect()
+}
+
+fn write_canonical_value(bytes: &mut Vec<u8>, value: &Value) {
+    value
+        .serialize_write(bytes)
+        .expect("ERROR: failed to write to an in-memory buffer");
+}
+
+fn write_canonical_asset(bytes: &mut Vec<u8>, asset: &AssetIdentifier) {
+    write_canonical_value(bytes, &Value::from(asset.contract_identifier.clone()));
+    bytes.push(asset.asset_name.len());
+    bytes.extend_from_slice(asset.asset_name.as_str().as_bytes());
+}
+
+fn read_canonical_bytes(reader: &mut &[u8], buffer: &mut [u8]) -> Result<()> {
+    reader
+        .read_exact(buffer)
+        .map_err(|_| InterpreterError::FailedToConstructAssetTable.into())
+}
+
+fn read_canonical_u32(reader: &mut &[u8]) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    read_canonical_bytes(reader, &mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_canonical_value(reader: &mut &[u8]) -> Result<Value> {
+    Value::deserialize_read(reader, None)
+        .map_err(|_| InterpreterError::FailedToConstructAssetTable.into())
+}
+
+fn read_canonical_amount(reader: &mut &[u8]) -> Result<u128> {
+    match read_canonical_value(reader)? {
+        Value::UInt(amount) => Ok(amount),
+        _ => Err(InterpreterError::FailedToConstructAssetTable.into()),
+    }
+}
+
+fn read_canonical_asset(reader: &mut &[u8]) -> Result<AssetIdentifier> {
+    let contract_identifier = match read_canonical_value(reader)? {
+        Value::Principal(PrincipalData::Contract(contract_identifier)) => contract_identifier,
+        _ => return Err(InterpreterError::FailedToConstructAssetTable.into()),
+    };
+    let mut name_length = [0u8; 1];
+    read_canonical_bytes(reader, &mut name_length)?;
+    let mut name = vec![0u8; name_length[0] as usize];
+    read_canonical_bytes(reader, &mut name)?;
+    let asset_name: ClarityName = String::from_utf8(name)
+        .ok()
+        .and_then(|name| name.try_into().ok())
+        .ok_or(InterpreterError::FailedToConstructAssetTable)?;
+    Ok(AssetIdentifier {
+        contract_identifier,
+        asset_name,
+    })
+}
+
+/// Displays an `AssetMap` with principals replaced by the names a resolver returns for
+///  them, falling back to the raw principal. See `AssetMap::display_with`.
+pub struct AssetMapDisplay<'a> {
+    asset_map: &'a AssetMap,
+    resolver: &'a dyn Fn(&PrincipalData) -> Option<String>,
+}
+
+impl AssetMap {
+    pub fn display_with<'a>(
+        &'a self,
+        resolver: &'a dyn Fn(&PrincipalData) -> Option<String>,
+    ) -> AssetMapDisplay<'a> {
+        AssetMapDisplay {
+            asset_map: self,
+            resolver,
+        }
+    }
+
+    fn fmt_with(
+        &self,
+        f: &mut fmt::Formatter,
+        resolver: &dyn Fn(&PrincipalData) -> Option<String>,
+    ) -> fmt::Result {
+        let name = |principal: &PrincipalData| {
+            resolver(principal).unwrap_or_else(|| principal.to_string())
+        };
+        write!(f, "[")?;
+        for (principal, principal_map) in self.token_map.iter() {
+            for (asset, amount) in principal_map.iter() {
+                write!(f, "{} spent {} {}\n", name(principal), amount, asset)?;
             }
         }
         for (principal, principal_map) in self.asset_map.iter() {
             for (asset, transfer) in principal_map.iter() {
-                write!(f, "{} transfered [", principal)?;
+                write!(f, "{} transfered [", name(principal))?;
                 for t in transfer {
                     write!(f, "{}, ", t)?;
                 }
@@ -426,49 +1516,85 @@ impl fmt::Display for AssetMap {
             }
         }
         for (principal, stx_amount) in self.stx_map.iter() {
-            write!(f, "{} spent {} microSTX\n", principal, stx_amount)?;
+            write!(f, "{} spent {} microSTX\n", name(principal), stx_amount)?;
         }
         for (principal, stx_burn_amount) in self.burn_map.iter() {
-            write!(f, "{} burned {} microSTX\n", principal, stx_burn_amount)?;
+            write!(
+                f,
+                "{} burned {} microSTX\n",
+                name(principal),
+                stx_burn_amount
+            )?;
         }
         write!(f, "]")
     }
 }
 
+impl fmt::Display for AssetMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with(f, &|_| None)
+    }
+}
+
+impl<'a> fmt::Display for AssetMapDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.asset_map.fmt_with(f, self.resolver)
+    }
+}
+
 impl EventBatch {
     pub fn new() -> EventBatch {
         EventBatch { events: vec![] }
     }
 }
 
+impl<'b, 'a: 'b> TransactionScope<'b, 'a> {
+    pub fn get_exec_environment<'c>(&'c mut self, sender: Option<Value>) -> Environment<'c, 'a> {
+        self.owned_env.get_exec_environment(sender)
+    }
+
+    pub fn commit(mut self) -> Result<(AssetMap, EventBatch)> {
+        self.finished = true;
+        self.owned_env.commit()
+    }
+}
+
+impl<'b, 'a: 'b> Drop for TransactionScope<'b, 'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.owned_env.context.roll_back();
+        }
+    }
+}
+
 impl<'a> OwnedEnvironment<'a> {
     #[cfg(test)]
     pub fn new(database: ClarityDatabase<'a>) -> OwnedEnvironment<'a> {
-        OwnedEnvironment {
-            context: GlobalContext::new(false, database, LimitedCostTracker::new_free()),
-            default_contract: ContractContext::new(QualifiedContractIdentifier::transient()),
-            call_stack: CallStack::new(),
-        }
+        OwnedEnvironment::with_config(database, OwnedEnvironmentConfig::default())
     }
 
     #[cfg(test)]
     pub fn new_max_limit(mut database: ClarityDatabase<'a>) -> OwnedEnvironment<'a> {
-        let cost_track = LimitedCostTracker::new_max_limit(&mut database)
+        let cost_tracker = LimitedCostTracker::new_max_limit(&mut database)
             .expect("FAIL: problem instantiating cost tracking");
 
-        OwnedEnvironment {
-            context: GlobalContext::new(false, database, cost_track),
-            default_contract: ContractContext::new(QualifiedContractIdentifier::transient()),
-            call_stack: CallStack::new(),
-        }
+        OwnedEnvironment::with_config(
+            database,
+            OwnedEnvironmentConfig {
+                cost_tracker,
+                ..OwnedEnvironmentConfig::default()
+            },
+        )
     }
 
     pub fn new_free(mainnet: bool, database: ClarityDatabase<'a>) -> OwnedEnvironment<'a> {
-        OwnedEnvironment {
-            context: GlobalContext::new(mainnet, database, LimitedCostTracker::new_free()),
-            default_contract: ContractContext::new(QualifiedContractIdentifier::transient()),
-            call_stack: CallStack::new(),
-        }
+        OwnedEnvironment::with_config(
+            database,
+            OwnedEnvironmentConfig {
+                mainnet,
+                ..OwnedEnvironmentConfig::default()
+            },
+        )
     }
 
     pub fn new_cost_limited(
@@ -476,8 +1602,31 @@ impl<'a> OwnedEnvironment<'a> {
         database: ClarityDatabase<'a>,
         cost_tracker: LimitedCostTracker,
     ) -> OwnedEnvironment<'a> {
+        OwnedEnvironment::with_config(
+            database,
+            OwnedEnvironmentConfig {
+                mainnet,
+                cost_tracker,
+                ..OwnedEnvironmentConfig::default()
+            },
+        )
+    }
+
+    pub fn with_config(
+        database: ClarityDatabase<'a>,
+        config: OwnedEnvironmentConfig,
+    ) -> OwnedEnvironment<'a> {
+        let mut context = GlobalContext::new(config.mainnet, database, config.cost_tracker);
+        context.reject_zero_transfers = config.reject_zero_transfers;
+        context.reject_duplicate_nft_transfers = config.reject_duplicate_nft_transfers;
+        context.max_asset_map_bytes = config.max_asset_map_bytes;
+        context.max_asset_map_entries = config.max_asset_map_entries;
+        context.simulate_writes = config.simulate_writes;
+        context.strict_response = config.strict_response;
+        context.record_transfer_log = config.record_transfer_log;
+
         OwnedEnvironment {
-            context: GlobalContext::new(mainnet, database, cost_tracker),
+            context,
             default_contract: ContractContext::new(QualifiedContractIdentifier::transient()),
             call_stack: CallStack::new(),
         }
@@ -493,17 +1642,39 @@ impl<'a> OwnedEnvironment<'a> {
         )
     }
 
+    pub fn transaction<'b>(&'b mut self) -> TransactionScope<'b, 'a> {
+        self.begin();
+        TransactionScope {
+            owned_env: self,
+            finished: false,
+        }
+    }
+
     pub fn execute_in_env<F, A, E>(
         &mut self,
         sender: Value,
         f: F,
     ) -> std::result::Result<(A, AssetMap, Vec<StacksTransactionEvent>), E>
+    where
+        E: From<::vm::errors::Error>,
+        F: FnOnce(&mut Environment) -> std::result::Result<A, E>,
+    {
+        let (return_value, asset_map, events, _) = self.execute_in_env_with_prints(sender, f)?;
+        Ok((return_value, asset_map, events))
+    }
+
+    fn execute_in_env_with_prints<F, A, E>(
+        &mut self,
+        sender: Value,
+        f: F,
+    ) -> std::result::Result<(A, AssetMap, Vec<StacksTransactionEvent>, Vec<Value>), E>
     where
         E: From<::vm::errors::Error>,
         F: FnOnce(&mut Environment) -> std::result::Result<A, E>,
     {
         assert!(self.context.is_top_level());
         self.begin();
+        self.call_stack.reset_high_water_mark();
 
         let result = {
             let mut exec_env = self.get_exec_environment(Some(sender));
@@ -512,8 +1683,8 @@ impl<'a> OwnedEnvironment<'a> {
 
         match result {
             Ok(return_value) => {
-                let (asset_map, event_batch) = self.commit()?;
-                Ok((return_value, asset_map, event_batch.events))
+                let (asset_map, event_batch, prints) = self.commit_with_prints()?;
+                Ok((return_value, asset_map, event_batch.events, prints))
             }
             Err(e) => {
                 self.context.roll_back();
@@ -551,6 +1722,31 @@ impl<'a> OwnedEnvironment<'a> {
         )
     }
 
+    /// Store a hand-built contract context (e.g., one doctored with `set_variable`) as a
+    ///  new contract, bypassing parsing and initialization.
+    #[cfg(feature = "developer-mode")]
+    pub fn install_contract_context(&mut self, contract_context: ContractContext) -> Result<()> {
+        let contract_identifier = contract_context.contract_identifier.clone();
+        self.execute_in_env(
+            Value::from(contract_identifier.issuer.clone()),
+            |exec_env| {
+                let database = &mut exec_env.global_context.database;
+                if database.has_contract(&contract_identifier) {
+                    return Err(CheckErrors::ContractAlreadyExists(
+                        contract_identifier.to_string(),
+                    )
+                    .into());
+                }
+                // there is no source, but the content hash must exist before any metadata.
+                database.insert_contract_hash(&contract_identifier, "")?;
+                let data_size = contract_context.data_size;
+                database.insert_contract(&contract_identifier, Contract { contract_context });
+                database.set_contract_data_size(&contract_identifier, data_size)
+            },
+        )
+        .map(|_| ())
+    }
+
     pub fn execute_transaction(
         &mut self,
         sender: Value,
@@ -563,6 +1759,250 @@ impl<'a> OwnedEnvironment<'a> {
         })
     }
 
+    /// Deploy `source` as `deployer`'s contract `name` and immediately call its public
+    ///  function `func` (e.g., a bootstrap call), as a single unit: if either step errors,
+    ///  or the call returns an `(err ...)`, the deployment is rolled back along with it.
+    pub fn deploy_and_call(
+        &mut self,
+        deployer: Value,
+        name: &str,
+        source: &str,
+        func: &str,
+        args: &[Value],
+    ) -> Result<(Value, AssetMap)> {
+        let issuer = match deployer {
+            Value::Principal(PrincipalData::Standard(ref issuer)) => issuer.clone(),
+            _ => {
+                return Err(
+                    CheckErrors::TypeValueError(TypeSignature::PrincipalType, deployer).into(),
+                )
+            }
+        };
+        let contract_identifier =
+            QualifiedContractIdentifier::new(issuer, name.to_string().try_into()?);
+        let args: Vec<SymbolicExpression> = args
+            .iter()
+            .map(|arg| SymbolicExpression::atom_value(arg.clone()))
+            .collect();
+
+        assert!(self.context.is_top_level());
+        self.begin();
+
+        let result = {
+            let mut exec_env = self.get_exec_environment(Some(deployer));
+            exec_env
+                .initialize_contract(contract_identifier.clone(), source)
+                .and_then(|_| exec_env.execute_contract(&contract_identifier, func, &args, false))
+        };
+
+        match result {
+            Ok(value) => match value.response_inner().map(|(committed, _)| committed) {
+                Some(true) => {
+                    let (asset_map, _) = self.commit()?;
+                    Ok((value, asset_map))
+                }
+                Some(false) => {
+                    self.context.roll_back();
+                    Ok((value, AssetMap::new()))
+                }
+                None => {
+                    self.context.roll_back();
+                    Err(
+                        CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::type_of(
+                            &value,
+                        ))
+                        .into(),
+                    )
+                }
+            },
+            Err(e) => {
+                self.context.roll_back();
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `execute_transaction`, but also returns the cost of the transaction in each of
+    ///  the cost dimensions (runtime, read count and length, write count and length).
+    pub fn execute_transaction_with_cost(
+        &mut self,
+        sender: Value,
+        contract_identifier: QualifiedContractIdentifier,
+        tx_name: &str,
+        args: &[SymbolicExpression],
+    ) -> Result<(Value, AssetMap, Vec<StacksTransactionEvent>, ExecutionCost)> {
+        let cost_before = self.context.cost_track.get_total();
+        let (result, asset_map, events) =
+            self.execute_transaction(sender, contract_identifier, tx_name, args)?;
+        let mut cost = self.context.cost_track.get_total();
+        cost.sub(&cost_before)?;
+        Ok((result, asset_map, events, cost))
+    }
+
+    /// Like `execute_transaction_with_cost`, but the returned cost is net of the refunds
+    ///  the transaction earned by freeing storage. Refunds are capped at half of the cost
+    ///  in each dimension (see `ExecutionCost::with_refund`).
+    pub fn execute_transaction_with_effective_cost(
+        &mut self,
+        sender: Value,
+        contract_identifier: QualifiedContractIdentifier,
+        tx_name: &str,
+        args: &[SymbolicExpression],
+    ) -> Result<(Value, AssetMap, Vec<StacksTransactionEvent>, ExecutionCost)> {
+        let (result, asset_map, events, cost) =
+            self.execute_transaction_with_cost(sender, contract_identifier, tx_name, args)?;
+        let effective_cost = cost.with_refund(self.context.last_refund());
+        Ok((result, asset_map, events, effective_cost))
+    }
+
+    /// Run the transaction exactly as `execute_transaction` would, check its asset map
+    ///  against `post_conditions` as block processing would (under `post_condition_mode`,
+    ///  which decides whether assets moved without a post-condition fail the check), and
+    ///  then roll everything back, including the cost charged. Fails only if the
+    ///  transaction itself fails (an err response is a successful simulation with an
+    ///  empty asset map).
+    pub fn simulate_transaction(
+        &mut self,
+        sender: Value,
+        contract_identifier: QualifiedContractIdentifier,
+        tx_name: &str,
+        args: &[SymbolicExpression],
+        post_condition_mode: &TransactionPostConditionMode,
+        post_conditions: &[TransactionPostCondition],
+    ) -> Result<SimulationResult> {
+        let principal = match sender {
+            Value::Principal(ref principal) => principal.clone(),
+            _ => return Err(InterpreterError::BadSender(sender).into()),
+        };
+
+        assert!(self.context.is_top_level());
+        let cost_track = self.context.cost_track.clone();
+        let cost_before = cost_track.get_total();
+
+        self.begin();
+        self.call_stack.reset_high_water_mark();
+        let result = {
+            let mut exec_env = self.get_exec_environment(Some(sender));
+            exec_env.execute_contract(&contract_identifier, tx_name, args, false)
+        };
+        let asset_map = replace(self.context.get_asset_map(), AssetMap::new());
+        let events = match self.context.event_batches.last_mut() {
+            Some(event_batch) => replace(&mut event_batch.events, Vec::new()),
+            None => Vec::new(),
+        };
+        let mut cost = self.context.cost_track.get_total();
+        self.context.roll_back();
+        self.context.cost_track = cost_track;
+
+        let value = result?;
+        cost.sub(&cost_before)?;
+
+        self.context.begin_read_only();
+        let origin_account = StacksAccount {
+            stx_balance: self.context.database.get_account_stx_balance(&principal),
+            nonce: self.context.database.get_account_nonce(&principal),
+            principal,
+        };
+        self.context.roll_back();
+        let post_conditions_passed = StacksChainState::check_transaction_postconditions(
+            post_conditions,
+            post_condition_mode,
+            &origin_account,
+            &asset_map,
+        );
+
+        Ok(SimulationResult {
+            value,
+            asset_map,
+            events,
+            cost,
+            post_conditions_passed,
+        })
+    }
+
+    /// Like `execute_transaction`, but also returns the change in the sender's available STX
+    ///  balance, followed by the change in its balance of each of `tokens`. Balances are
+    ///  read from the database before the transaction begins and after it commits, so the
+    ///  deltas can be cross-checked against the returned asset map.
+    pub fn execute_transaction_with_balance_delta(
+        &mut self,
+        sender: Value,
+        contract_identifier: QualifiedContractIdentifier,
+        tx_name: &str,
+        args: &[SymbolicExpression],
+        tokens: &[AssetIdentifier],
+    ) -> Result<(Value, AssetMap, Vec<StacksTransactionEvent>, Vec<i128>)> {
+        let principal = match sender {
+            Value::Principal(ref principal) => principal.clone(),
+            _ => return Err(InterpreterError::BadSender(sender).into()),
+        };
+
+        let before = self.read_balances(&principal, tokens)?;
+        let (result, asset_map, events) =
+            self.execute_transaction(sender, contract_identifier, tx_name, args)?;
+        let after = self.read_balances(&principal, tokens)?;
+
+        let deltas = before
+            .into_iter()
+            .zip(after.into_iter())
+            .map(|(before, after)| balance_delta(before, after))
+            .collect::<Result<_>>()?;
+        Ok((result, asset_map, events, deltas))
+    }
+
+    fn read_balances(
+        &mut self,
+        principal: &PrincipalData,
+        tokens: &[AssetIdentifier],
+    ) -> Result<Vec<u128>> {
+        self.context.begin_read_only();
+        let result = fetch_balances(&mut self.context.database, principal, tokens);
+        self.context.roll_back();
+        result
+    }
+
+    /// Like `execute_transaction`, but also returns the values the transaction passed to
+    ///  `print`. Prints made in a context that was later rolled back are not included.
+    pub fn execute_transaction_with_prints(
+        &mut self,
+        sender: Value,
+        contract_identifier: QualifiedContractIdentifier,
+        tx_name: &str,
+        args: &[SymbolicExpression],
+    ) -> Result<(Value, AssetMap, Vec<Value>)> {
+        let (result, asset_map, _, prints) = self
+            .execute_in_env_with_prints(sender, |exec_env| {
+                exec_env.execute_contract(&contract_identifier, tx_name, args, false)
+            })?;
+        Ok((result, asset_map, prints))
+    }
+
+    /// Like `execute_transaction`, but unwraps the public function's response: `Ok` holds
+    ///  the data of a committed `(ok ...)`, `Err` the data of an aborted `(err ...)`.
+    pub fn execute_transaction_checked(
+        &mut self,
+        sender: Value,
+        contract_identifier: QualifiedContractIdentifier,
+        tx_name: &str,
+        args: &[SymbolicExpression],
+    ) -> Result<(std::result::Result<Value, Value>, AssetMap)> {
+        let (result, asset_map, _) =
+            self.execute_transaction(sender, contract_identifier, tx_name, args)?;
+        match result {
+            Value::Response(data) => {
+                if data.committed {
+                    Ok((Ok(*data.data), asset_map))
+                } else {
+                    Ok((Err(*data.data), asset_map))
+                }
+            }
+            _ => Err(
+                CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::type_of(&result))
+                    .into(),
+            ),
+        }
+    }
+
     pub fn stx_transfer(
         &mut self,
         from: &PrincipalData,
@@ -607,6 +2047,41 @@ impl<'a> OwnedEnvironment<'a> {
         .unwrap();
     }
 
+    /// Credit `amount` of the fungible token `asset` to `principal`, writing the balance and
+    ///  token supply directly rather than through the token contract's logic.
+    #[cfg(test)]
+    pub fn credit_token(
+        &mut self,
+        principal: &PrincipalData,
+        asset: &AssetIdentifier,
+        amount: u128,
+    ) -> Result<()> {
+        self.execute_in_env(principal.clone().into(), |env| {
+            let database = &mut env.global_context.database;
+            let token_name = asset.asset_name.as_str();
+            let descriptor = database.load_ft(&asset.contract_identifier, token_name)?;
+
+            database.checked_increase_token_supply(
+                &asset.contract_identifier,
+                token_name,
+                amount,
+                &descriptor,
+            )?;
+
+            let balance = database.get_ft_balance(
+                &asset.contract_identifier,
+                token_name,
+                principal,
+                Some(&descriptor),
+            )?;
+            let balance = balance
+                .checked_add(amount)
+                .ok_or(RuntimeErrorType::ArithmeticOverflow)?;
+            database.set_ft_balance(&asset.contract_identifier, token_name, principal, balance)
+        })
+        .map(|_| ())
+    }
+
     #[cfg(test)]
     pub fn eval_raw(
         &mut self,
@@ -633,14 +2108,80 @@ impl<'a> OwnedEnvironment<'a> {
         self.context.begin();
     }
 
-    pub fn commit(&mut self) -> Result<(AssetMap, EventBatch)> {
-        let (asset_map, event_batch) = self.context.commit()?;
-        let asset_map = asset_map.ok_or(InterpreterError::FailedToConstructAssetTable)?;
-        let event_batch = event_batch.ok_or(InterpreterError::FailedToConstructEventBatch)?;
+    pub fn abort_handle(&self) -> Arc<AtomicBool> {
+        self.context.abort_handle()
+    }
 
+    pub fn commit(&mut self) -> Result<(AssetMap, EventBatch)> {
+        let (asset_map, event_batch, _) = self.commit_with_prints()?;
         Ok((asset_map, event_batch))
     }
 
+    fn commit_with_prints(&mut self) -> Result<(AssetMap, EventBatch, Vec<Value>)> {
+        match self.context.commit()? {
+            CommitOutcome::TopLevel(asset_map, event_batch, prints) => {
+                Ok((asset_map, event_batch, prints))
+            }
+            CommitOutcome::MergedUpward => {
+                Err(InterpreterError::FailedToConstructAssetTable.into())
+            }
+        }
+    }
+
+    /// Pin the deployed contract `contract_name` (e.g., `"SP000000000000000000002Q6VF78.pox"`)
+    ///  in memory. See `GlobalContext::pin_contract`.
+    pub fn pin_contract(&mut self, contract_name: &str) -> Result<()> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        self.context.pin_contract(&contract_identifier)
+    }
+
+    /// Release a contract pinned by `pin_contract`. Returns whether it was pinned.
+    pub fn unpin_contract(&mut self, contract_name: &str) -> Result<bool> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        Ok(self.context.unpin_contract(&contract_identifier))
+    }
+
+    /// Make calls to `function_name` in the contract `contract_name` return `result`
+    ///  without executing it. See `GlobalContext::mock_contract_call`.
+    #[cfg(feature = "developer-mode")]
+    pub fn mock_contract_call(
+        &mut self,
+        contract_name: &str,
+        function_name: &str,
+        result: Value,
+    ) -> Result<()> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        let function_name: ClarityName = function_name.to_string().try_into()?;
+        self.context
+            .mock_contract_call(&contract_identifier, function_name, result)
+    }
+
+    /// Remove a mock installed by `mock_contract_call`. Returns whether it was mocked.
+    #[cfg(feature = "developer-mode")]
+    pub fn unmock_contract_call(
+        &mut self,
+        contract_name: &str,
+        function_name: &str,
+    ) -> Result<bool> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        let function_name: ClarityName = function_name.to_string().try_into()?;
+        Ok(self
+            .context
+            .unmock_contract_call(&contract_identifier, &function_name))
+    }
+
+    /// Whether the most recent transaction (e.g., via `execute_transaction`) wrote to the
+    ///  database or moved assets. See `GlobalContext::had_side_effects`.
+    pub fn had_side_effects(&self) -> bool {
+        self.context.had_side_effects()
+    }
+
+    /// The deepest call stack reached by the most recent transaction (e.g., via
+    ///  `execute_transaction`), whether or not it succeeded.
+    pub fn max_call_stack_depth(&self) -> usize {
+        self.call_stack.max_depth_reached()
+    }
+
     /// Destroys this environment, returning ownership of its database reference.
     ///  If the context wasn't top-level (i.e., it had uncommitted data), return None,
     ///   because the database is not guaranteed to be in a sane state.
@@ -749,27 +2290,199 @@ impl<'a, 'b> Environment<'a, 'b> {
             call_stack,
             sender,
             caller,
+            callers: Vec::new(),
         }
     }
 
+    pub fn current_contract_name(&self) -> &ContractName {
+        &self.contract_context.contract_identifier.name
+    }
+
+    /// True when evaluating outside of any deployed contract (e.g., a top-level eval).
+    pub fn is_transient(&self) -> bool {
+        self.current_contract_name().as_str() == TRANSIENT_CONTRACT_NAME
+    }
+
+    /// The headroom left before evaluating in `context` would hit `MAX_CONTEXT_DEPTH`
+    ///  and `MAX_CALL_STACK_DEPTH`, as (local context depth, call stack depth).
+    pub fn depth_remaining(&self, context: &LocalContext) -> (u16, usize) {
+        (
+            MAX_CONTEXT_DEPTH.saturating_sub(context.depth()),
+            MAX_CALL_STACK_DEPTH.saturating_sub(self.call_stack.depth()),
+        )
+    }
+
+    /// The principal of the currently-executing contract, i.e., the principal that
+    ///  `as-contract` and contract-calls made from this contract act as.
+    pub fn contract_principal(&self) -> PrincipalData {
+        PrincipalData::Contract(self.contract_context.contract_identifier.clone())
+    }
+
     pub fn nest_as_principal<'c>(&'c mut self, sender: Value) -> Environment<'c, 'b> {
-        Environment::new(
+        let callers = self.caller_chain();
+        let mut nested_env = Environment::new(
             self.global_context,
             self.contract_context,
             self.call_stack,
             Some(sender.clone()),
             Some(sender),
-        )
+        );
+        nested_env.callers = callers;
+        nested_env
     }
 
     pub fn nest_with_caller<'c>(&'c mut self, caller: Value) -> Environment<'c, 'b> {
-        Environment::new(
+        let callers = self.caller_chain();
+        let mut nested_env = Environment::new(
             self.global_context,
             self.contract_context,
             self.call_stack,
             self.sender.clone(),
             Some(caller),
-        )
+        );
+        nested_env.callers = callers;
+        nested_env
+    }
+
+    /// Every caller that led to this environment, outermost (usually the transaction's
+    ///  sender) first and ending with the current `caller`. Each `nest_with_caller` and
+    ///  `nest_as_principal` (e.g., for `contract-call?` and `as-contract`) adds the caller
+    ///  it replaces.
+    pub fn caller_chain(&self) -> Vec<PrincipalData> {
+        let mut chain = self.callers.clone();
+        if let Some(Value::Principal(ref caller)) = self.caller {
+            chain.push(caller.clone());
+        }
+        chain
+    }
+
+    /// What's left of the cost budget in each dimension, e.g., for batch operations to stop
+    ///  before exceeding it. `None` if the budget is unlimited (e.g., with a free tracker).
+    pub fn budget_remaining(&self) -> Option<ExecutionCost> {
+        let limit = self.global_context.cost_track.get_limit();
+        if limit == ExecutionCost::max_value() {
+            return None;
+        }
+        let total = self.global_context.cost_track.get_total();
+        Some(ExecutionCost {
+            write_length: limit.write_length.saturating_sub(total.write_length),
+            write_count: limit.write_count.saturating_sub(total.write_count),
+            read_length: limit.read_length.saturating_sub(total.read_length),
+            read_count: limit.read_count.saturating_sub(total.read_count),
+            runtime: limit.runtime.saturating_sub(total.runtime),
+        })
+    }
+
+    /// The current value of the data variable `var_name` of the contract `contract_name`
+    ///  (fully qualified, e.g., `SP2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKNRV9EJ7.contract`), read
+    ///  directly from the database without evaluating any Clarity. Returns `None` if the
+    ///  contract or the variable doesn't exist. The read is not charged to the cost tracker.
+    pub fn get_data_var(&mut self, contract_name: &str, var_name: &str) -> Result<Option<Value>> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        let database = &mut self.global_context.database;
+        match database.lookup_variable_unknown_descriptor(&contract_identifier, var_name) {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::Unchecked(CheckErrors::NoSuchDataVariable(_))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The value stored under `key` in the map `map_name` of the contract `contract_name`
+    ///  (fully qualified, as in `get_data_var`), read directly from the database like
+    ///  `map-get?` would. Returns `None` if there is no such entry, or if the contract or
+    ///  the map doesn't exist. The read is not charged to the cost tracker.
+    pub fn get_map_entry(
+        &mut self,
+        contract_name: &str,
+        map_name: &str,
+        key: &Value,
+    ) -> Result<Option<Value>> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        let database = &mut self.global_context.database;
+        match database.fetch_entry_unknown_descriptor(&contract_identifier, map_name, key) {
+            Ok(entry) => Ok(entry.expect_optional()),
+            Err(Error::Unchecked(CheckErrors::NoSuchMap(_))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `get_map_entry`, but reads the entries under each of `keys` (in order), loading
+    ///  the map's metadata once and performing every read within a single read-only context.
+    pub fn get_map_entries(
+        &mut self,
+        contract_name: &str,
+        map_name: &str,
+        keys: &[Value],
+    ) -> Result<Vec<Option<Value>>> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+
+        self.global_context.begin_read_only();
+        let result = fetch_map_entries(
+            &mut self.global_context.database,
+            &contract_identifier,
+            map_name,
+            keys,
+        );
+        self.global_context.roll_back();
+
+        result
+    }
+
+    /// Runs `f` in a nested environment whose caller is the contract `contract`, deployed
+    ///  by the current contract's issuer (i.e., the contract `.contract` refers to here).
+    ///  The sender is unchanged.
+    pub fn as_contract_caller<F, T>(&mut self, contract: &ContractName, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Environment) -> Result<T>,
+    {
+        let contract_identifier = QualifiedContractIdentifier::new(
+            self.contract_context.contract_identifier.issuer.clone(),
+            contract.clone(),
+        );
+        let mut nested_env = self.nest_with_caller(Value::Principal(PrincipalData::Contract(
+            contract_identifier,
+        )));
+        f(&mut nested_env)
+    }
+
+    /// Logs a transfer of `amount` of the fungible token `asset` from the current contract
+    ///  to `recipient`, as an `ft-transfer?` within `as-contract` would, so that test
+    ///  harnesses can exercise as-contract transfer accounting directly. The transfer is
+    ///  recorded in the asset map and emitted as an event, but balances in the database
+    ///  are left untouched.
+    pub fn transfer_as_contract(
+        &mut self,
+        asset: &AssetIdentifier,
+        recipient: &PrincipalData,
+        amount: u128,
+    ) -> Result<()> {
+        let sender = self.contract_principal();
+        self.global_context.log_token_transfer(
+            &sender,
+            &asset.contract_identifier,
+            &asset.asset_name,
+            amount,
+        )?;
+        self.register_ft_transfer_event(sender, recipient.clone(), amount, asset.clone())
+    }
+
+    /// Executes a sequence of public function calls against the current contract, each
+    ///  step under its own sender. Every step shares this environment's global context,
+    ///  so state written by one step is visible to the next. Aborts on the first error.
+    pub fn run_as_sequence(&mut self, steps: &[(Value, String, Vec<Value>)]) -> Result<Vec<Value>> {
+        let contract_identifier = self.contract_context.contract_identifier.clone();
+        let mut results = Vec::with_capacity(steps.len());
+        for (sender, tx_name, args) in steps.iter() {
+            let args: Vec<SymbolicExpression> = args
+                .iter()
+                .map(|arg| SymbolicExpression::atom_value(arg.clone()))
+                .collect();
+            let mut nested_env = self.nest_as_principal(sender.clone());
+            let result =
+                nested_env.execute_contract(&contract_identifier, tx_name, &args, false)?;
+            results.push(result);
+        }
+        Ok(results)
     }
 
     pub fn eval_read_only(
@@ -777,7 +2490,18 @@ impl<'a, 'b> Environment<'a, 'b> {
         contract_identifier: &QualifiedContractIdentifier,
         program: &str,
     ) -> Result<Value> {
-        let parsed = ast::build_ast(contract_identifier, program, self)?.expressions;
+        self.eval_read_only_simulated(contract_identifier, program)
+            .map(|(value, _)| value)
+    }
+
+    /// Like `eval_read_only`, but also returns the asset transfers the evaluation
+    ///  logged before it was rolled back, i.e., what the program *would* have moved.
+    pub fn eval_read_only_simulated(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        program: &str,
+    ) -> Result<(Value, AssetMap)> {
+        let parsed = self.parse_program(contract_identifier, program)?;
 
         if parsed.len() < 1 {
             return Err(RuntimeErrorType::ParseError(
@@ -788,10 +2512,14 @@ impl<'a, 'b> Environment<'a, 'b> {
 
         self.global_context.begin();
 
-        let contract = self
-            .global_context
-            .database
-            .get_contract(contract_identifier)?;
+        // the contract's metadata lookup fails with `NoSuchContract` if it doesn't exist.
+        let contract = match self.global_context.load_contract(contract_identifier) {
+            Ok(contract) => contract,
+            Err(e) => {
+                self.global_context.roll_back();
+                return Err(e);
+            }
+        };
 
         let result = {
             let mut nested_env = Environment::new(
@@ -801,30 +2529,156 @@ impl<'a, 'b> Environment<'a, 'b> {
                 self.sender.clone(),
                 self.caller.clone(),
             );
+            nested_env.callers = self.callers.clone();
             let local_context = LocalContext::new();
             eval(&parsed[0], &mut nested_env, &local_context)
         };
 
+        // the asset map is discarded by the roll back anyways, so just take it.
+        let asset_map = replace(self.global_context.get_asset_map(), AssetMap::new());
         self.global_context.roll_back();
 
-        result
+        result.map(|value| (value, asset_map))
     }
 
+    /// The type `program`, which must be a single expression, would evaluate to in the
+    ///  context of the contract `contract_name`, without evaluating it. The program is
+    ///  type-checked against the contract's stored analysis, so nothing is written and no
+    ///  cost is charged. The analyses of contracts the program names directly are loaded for
+    ///  its `contract-call?`s; fails with `NoSuchContract` if the contract has no stored
+    ///  analysis.
+    pub fn type_of(&mut self, contract_name: &str, program: &str) -> Result<TypeSignature> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+
+        let parsed = ast::build_ast(&contract_identifier, program, &mut ())?.expressions;
+        if parsed.len() < 1 {
+            return Err(RuntimeErrorType::ParseError(
+                "Expected a program of at least length 1".to_string(),
+            )
+            .into());
+        }
+        if parsed.len() > 1 {
+            return Err(RuntimeErrorType::MultipleExpressions.into());
+        }
+
+        let mut referenced_contracts = HashSet::new();
+        depth_traverse::<_, _, ()>(&parsed[0], |expression| {
+            if let SymbolicExpressionType::LiteralValue(Value::Principal(
+                PrincipalData::Contract(ref contract_identifier),
+            )) = expression.expr
+            {
+                referenced_contracts.insert(contract_identifier.clone());
+            }
+            Ok(())
+        })
+        .expect("BUG: infallible traversal failed");
+
+        self.global_context.begin_read_only();
+        let contract_analysis = self
+            .global_context
+            .database
+            .load_contract_analysis(&contract_identifier);
+        let referenced_analyses: Vec<ContractAnalysis> = referenced_contracts
+            .iter()
+            .filter_map(|referenced| {
+                self.global_context
+                    .database
+                    .load_contract_analysis(referenced)
+            })
+            .collect();
+        self.global_context.roll_back();
+        let contract_analysis = contract_analysis
+            .ok_or_else(|| CheckErrors::NoSuchContract(contract_identifier.to_string()))?;
+
+        // the type checker resolves `contract-call?`s through an analysis database, so stage
+        //  the referenced analyses in a scratch one, which is never committed.
+        let mut store = MemoryBackingStore::new();
+        let mut analysis_db = store.as_analysis_db();
+        analysis_db.begin();
+        let result = referenced_analyses
+            .iter()
+            .try_for_each(|referenced| {
+                analysis_db.insert_contract(&referenced.contract_identifier, referenced)
+            })
+            .and_then(|_| {
+                TypeChecker::type_check_in_contract(
+                    &parsed[0],
+                    &contract_analysis,
+                    &mut analysis_db,
+                    LimitedCostTracker::new_free(),
+                )
+            });
+        analysis_db.roll_back();
+
+        result.map_err(|e| Error::Unchecked(e.err))
+    }
+
+    /// Evaluate `program`, which must be a single expression, outside of any contract.
+    ///  Fails with `RuntimeErrorType::MultipleExpressions` rather than ignoring trailing
+    ///  expressions.
     pub fn eval_raw(&mut self, program: &str) -> Result<Value> {
         let contract_id = QualifiedContractIdentifier::transient();
 
-        let parsed = ast::build_ast(&contract_id, program, self)?.expressions;
+        let parsed = self.parse_program(&contract_id, program)?;
         if parsed.len() < 1 {
             return Err(RuntimeErrorType::ParseError(
                 "Expected a program of at least length 1".to_string(),
             )
             .into());
         }
+        if parsed.len() > 1 {
+            return Err(RuntimeErrorType::MultipleExpressions.into());
+        }
         let local_context = LocalContext::new();
         let result = { eval(&parsed[0], self, &local_context) };
         result
     }
 
+    fn parse_program(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        program: &str,
+    ) -> Result<Vec<SymbolicExpression>> {
+        if let Some(ref mut parse_cache) = self.global_context.parse_cache {
+            if let Some(parsed) = parse_cache.get(contract_identifier, program) {
+                return Ok(parsed);
+            }
+        }
+
+        let parsed = ast::build_ast(contract_identifier, program, self)?.expressions;
+
+        if let Some(ref mut parse_cache) = self.global_context.parse_cache {
+            parse_cache.insert(contract_identifier, program, parsed.clone());
+        }
+        Ok(parsed)
+    }
+
+    /// The parsed expressions of a deployed contract, rebuilt from its stored source, so
+    ///  that tooling (e.g., linters or coverage tools) can map runtime events back to
+    ///  source expressions. Parsing is not charged to the cost tracker.
+    #[cfg(feature = "developer-mode")]
+    pub fn get_contract_ast(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+    ) -> Result<Vec<SymbolicExpression>> {
+        let contract_src = self
+            .global_context
+            .database
+            .get_contract_src(contract_identifier)
+            .ok_or_else(|| CheckErrors::NoSuchContract(contract_identifier.to_string()))?;
+        let contract_ast =
+            self.run_free(|env| ast::build_ast(contract_identifier, &contract_src, env))?;
+        Ok(contract_ast.expressions)
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    pub fn get_contract_ast(
+        &mut self,
+        _contract_identifier: &QualifiedContractIdentifier,
+    ) -> Result<Vec<SymbolicExpression>> {
+        Err(InterpreterError::ASTRetentionDisabled.into())
+    }
+
     /// Used only for contract-call! cost short-circuiting. Once the short-circuited cost
     ///  has been evaluated and assessed, the contract-call! itself is executed "for free".
     pub fn run_free<F, A>(&mut self, to_run: F) -> A
@@ -842,6 +2696,34 @@ impl<'a, 'b> Environment<'a, 'b> {
         result
     }
 
+    /// Runs `to_run` with `function` pushed onto the call stack, popping it again
+    ///  afterwards whether or not `to_run` succeeded.
+    pub fn with_call_stack_frame<F, T>(
+        &mut self,
+        function: &FunctionIdentifier,
+        track: bool,
+        to_run: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&mut Environment) -> Result<T>,
+    {
+        self.call_stack.insert(
+            function,
+            &self.contract_context.contract_identifier.name,
+            track,
+        );
+        // note: the frame must be removed before this method returns, even on error.
+        //  DO NOT use the try syntax (?) on to_run's result.
+        let result = to_run(self);
+        self.call_stack.remove(function, track)?;
+        result
+    }
+
+    /// Calls the public or read-only function `tx_name`. When `read_only` is set, only
+    ///  read-only functions may be called. A read-only function is always evaluated in a
+    ///  read-only context (see `execute_function_as_transaction`), even when called as a
+    ///  transaction: any write it attempts fails with `WriteAttemptedInReadOnly`, and its
+    ///  return value is passed through rather than handled as a committed/aborted response.
     pub fn execute_contract(
         &mut self,
         contract_identifier: &QualifiedContractIdentifier,
@@ -849,6 +2731,8 @@ impl<'a, 'b> Environment<'a, 'b> {
         args: &[SymbolicExpression],
         read_only: bool,
     ) -> Result<Value> {
+        // like every contract metadata lookup, this fails with `NoSuchContract` if the
+        //  contract doesn't exist.
         let contract_size = self
             .global_context
             .database
@@ -858,9 +2742,10 @@ impl<'a, 'b> Environment<'a, 'b> {
         self.global_context.add_memory(contract_size)?;
 
         finally_drop_memory!(self.global_context, contract_size; {
-            let contract = self.global_context.database.get_contract(contract_identifier)?;
+            let contract = self.global_context.load_contract(contract_identifier)?;
 
-            let func = contract.contract_context.lookup_function(tx_name)
+            // borrow the function out of the loaded contract rather than cloning its body.
+            let func = contract.contract_context.functions.get(tx_name)
                 .ok_or_else(|| { CheckErrors::UndefinedFunction(tx_name.to_string()) })?;
             if !func.is_public() {
                 return Err(CheckErrors::NoSuchPublicFunction(contract_identifier.to_string(), tx_name.to_string()).into());
@@ -868,24 +2753,15 @@ impl<'a, 'b> Environment<'a, 'b> {
                 return Err(CheckErrors::PublicFunctionNotReadOnly(contract_identifier.to_string(), tx_name.to_string()).into());
             }
 
-            let args: Result<Vec<Value>> = args.iter()
-                .map(|arg| {
-                    let value = arg.match_atom_value()
-                        .ok_or_else(|| InterpreterError::InterpreterError(format!("Passed non-value expression to exec_tx on {}!",
-                                                                                  tx_name)))?;
-                    Ok(value.clone())
-                })
-                .collect();
-
-            let args = args?;
+            let args = extract_atom_values(args)?;
 
             let func_identifier = func.get_identifier();
             if self.call_stack.contains(&func_identifier) {
                 return Err(CheckErrors::CircularReference(vec![func_identifier.to_string()]).into())
             }
-            self.call_stack.insert(&func_identifier, true);
-            let res = self.execute_function_as_transaction(&func, &args, Some(&contract.contract_context));
-            self.call_stack.remove(&func_identifier, true)?;
+            let res = self.with_call_stack_frame(&func_identifier, true, |env| {
+                env.execute_function_as_transaction(func, &args, Some(&contract.contract_context))
+            });
 
             match res {
                 Ok(value) => {
@@ -914,16 +2790,37 @@ impl<'a, 'b> Environment<'a, 'b> {
 
         let next_contract_context = next_contract_context.unwrap_or(self.contract_context);
 
-        let result = {
-            let mut nested_env = Environment::new(
-                &mut self.global_context,
-                next_contract_context,
-                self.call_stack,
-                self.sender.clone(),
-                self.caller.clone(),
-            );
+        self.global_context.observe_contract_call(
+            &self.contract_context.contract_identifier,
+            &next_contract_context.contract_identifier,
+            function.get_name(),
+        );
+
+        #[cfg(feature = "developer-mode")]
+        let mocked_result = self.global_context.get_mocked_call(
+            &next_contract_context.contract_identifier,
+            function.get_name(),
+        );
+        #[cfg(not(feature = "developer-mode"))]
+        let mocked_result: Option<Value> = None;
+
+        let result = match mocked_result {
+            Some(value) => Ok(value),
+            None => {
+                let mut nested_env = Environment::new(
+                    &mut self.global_context,
+                    next_contract_context,
+                    self.call_stack,
+                    self.sender.clone(),
+                    self.caller.clone(),
+                );
+                nested_env.callers = self.callers.clone();
 
-            function.execute_apply(args, &mut nested_env)
+                #[cfg(feature = "developer-mode")]
+                nested_env.check_breakpoint(function);
+
+                function.execute_apply(args, &mut nested_env)
+            }
         };
 
         if make_read_only {
@@ -934,6 +2831,24 @@ impl<'a, 'b> Environment<'a, 'b> {
         }
     }
 
+    #[cfg(feature = "developer-mode")]
+    fn check_breakpoint(&mut self, function: &DefinedFunction) {
+        if self.global_context.breakpoints.is_empty()
+            || !self
+                .global_context
+                .breakpoints
+                .contains(&function.get_identifier())
+        {
+            return;
+        }
+        // the callback is taken out for the call, since it can't borrow the environment
+        //  that owns it.
+        if let Some(mut callback) = self.global_context.debug_callback.take() {
+            callback(self);
+            self.global_context.debug_callback = Some(callback);
+        }
+    }
+
     pub fn evaluate_at_block(
         &mut self,
         bhh: StacksBlockId,
@@ -971,6 +2886,9 @@ impl<'a, 'b> Environment<'a, 'b> {
         self.initialize_contract_from_ast(contract_identifier, &contract_ast, &contract_content)
     }
 
+    /// Initializes the contract in a context nested within the current one, so it may be
+    ///  called from inside an open transaction (e.g., to deploy several contracts that
+    ///  commit or roll back together).
     pub fn initialize_contract_from_ast(
         &mut self,
         contract_identifier: QualifiedContractIdentifier,
@@ -1036,6 +2954,88 @@ impl<'a, 'b> Environment<'a, 'b> {
         }
     }
 
+    /// Like `initialize_contract`, but also stores `metadata` for tooling (e.g., a source
+    ///  hash, compiler version, or deploy timestamp) alongside the contract, outside of
+    ///  its source and of consensus data. Read it back with `get_contract_metadata`.
+    pub fn initialize_contract_with_metadata(
+        &mut self,
+        contract_identifier: QualifiedContractIdentifier,
+        contract_content: &str,
+        metadata: HashMap<String, String>,
+    ) -> Result<()> {
+        self.global_context.begin();
+        match self.initialize_contract(contract_identifier.clone(), contract_content) {
+            Ok(()) => {
+                self.global_context
+                    .database
+                    .insert_contract_tool_metadata(&contract_identifier, &metadata);
+                self.global_context.commit()?;
+                Ok(())
+            }
+            Err(e) => {
+                self.global_context.roll_back();
+                Err(e)
+            }
+        }
+    }
+
+    /// The metadata stored with the contract `contract_name` (fully qualified, as in
+    ///  `get_data_var`) by `initialize_contract_with_metadata`, or an empty map if it was
+    ///  deployed without any.
+    pub fn get_contract_metadata(
+        &mut self,
+        contract_name: &str,
+    ) -> Result<HashMap<String, String>> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        self.global_context
+            .database
+            .get_contract_tool_metadata(&contract_identifier)
+    }
+
+    /// The textual contract principal (e.g., `SP000000000000000000002Q6VF78.pox`) of the
+    ///  contract `name` deployed by the current contract's issuer, i.e., what `.name`
+    ///  refers to in the current contract.
+    pub fn contract_principal_string(&self, name: &ContractName) -> String {
+        QualifiedContractIdentifier::new(
+            self.contract_context.contract_identifier.issuer.clone(),
+            name.clone(),
+        )
+        .to_string()
+    }
+
+    /// The Clarity source of the contract `contract_name` (fully qualified, as in
+    ///  `get_data_var`), or `None` if this node did not retain it.
+    pub fn get_contract_source(&mut self, contract_name: &str) -> Result<Option<String>> {
+        let contract_identifier = QualifiedContractIdentifier::parse(contract_name)?;
+        let database = &mut self.global_context.database;
+        if !database.has_contract(&contract_identifier) {
+            return Err(CheckErrors::NoSuchContract(contract_identifier.to_string()).into());
+        }
+        Ok(database.get_contract_src(&contract_identifier))
+    }
+
+    /// Checks that every asset moved in `map` is defined by a deployed contract, failing
+    ///  with `NoSuchContract` for the first (by contract identifier) that isn't. STX
+    ///  transfers and burns are not checked.
+    pub fn validate_asset_map_contracts(&mut self, map: &AssetMap) -> Result<()> {
+        let mut contract_identifiers: Vec<&QualifiedContractIdentifier> = map
+            .asset_identifiers()
+            .into_iter()
+            .map(|asset| &asset.contract_identifier)
+            .collect();
+        contract_identifiers
+            .sort_by_cached_key(|contract_identifier| contract_identifier.to_string());
+        contract_identifiers.dedup();
+
+        let database = &mut self.global_context.database;
+        for contract_identifier in contract_identifiers.into_iter() {
+            if !database.has_contract(contract_identifier) {
+                return Err(CheckErrors::NoSuchContract(contract_identifier.to_string()).into());
+            }
+        }
+        Ok(())
+    }
+
     /// Top-level STX-transfer, invoked by TokenTransfer transactions.
     /// Only commits if the inner stx_transfer_consolidated() returns an (ok true) value.
     /// Rolls back if it returns an (err ..) value, or if the method itself fails for some reason
@@ -1088,6 +3088,8 @@ impl<'a, 'b> Environment<'a, 'b> {
     }
 
     pub fn register_print_event(&mut self, value: Value) -> Result<()> {
+        self.global_context.log_print(value.clone());
+
         let print_event = SmartContractEventData {
             key: (
                 self.contract_context.contract_identifier.clone(),
@@ -1278,13 +3280,281 @@ impl<'a> GlobalContext<'a> {
             asset_maps: Vec::new(),
             event_batches: Vec::new(),
             mainnet,
+            reject_zero_transfers: false,
+            reject_duplicate_nft_transfers: false,
+            max_asset_map_bytes: None,
+            max_asset_map_entries: None,
+            simulate_writes: false,
+            strict_response: true,
+            record_transfer_log: false,
+            cost_refunds: Vec::new(),
+            last_refund: ExecutionCost::zero(),
+            abort: Arc::new(AtomicBool::new(false)),
+            print_sinks: Vec::new(),
+            context_ids: Vec::new(),
+            next_context_id: 0,
+            coverage: None,
+            contract_call_observer: None,
+            parse_cache: None,
+            had_side_effects: false,
+            pinned_contracts: HashMap::new(),
+            warnings: Vec::new(),
+            #[cfg(feature = "developer-mode")]
+            mocked_calls: HashMap::new(),
+            #[cfg(feature = "developer-mode")]
+            breakpoints: HashSet::new(),
+            #[cfg(feature = "developer-mode")]
+            debug_callback: None,
+        }
+    }
+
+    /// A shared handle to this context's abort flag. Setting the flag from any thread
+    ///  causes evaluation to stop with `RuntimeErrorType::Aborted` at the next function
+    ///  application, rolling back the transaction.
+    pub fn abort_handle(&self) -> Arc<AtomicBool> {
+        self.abort.clone()
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.abort.load(Ordering::SeqCst)
+    }
+
+    /// Record the id of every `SymbolicExpression` evaluated from here on, e.g., for
+    ///  coverage reports over the expressions returned by `Environment::get_contract_ast`.
+    ///  Retrieve them with `take_coverage`.
+    pub fn enable_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(HashSet::new());
+        }
+    }
+
+    /// Cache the parsed programs of up to `capacity` distinct `eval_read_only` and `eval_raw`
+    ///  calls, so that repeated queries (e.g., from a read-only RPC endpoint) skip parsing.
+    ///  Parse costs are _not_ charged on a cache hit, so this must never be enabled when
+    ///  evaluating consensus-critical code.
+    pub fn enable_parse_cache(&mut self, capacity: usize) {
+        self.parse_cache = Some(ParseCache::new(capacity));
+    }
+
+    /// Keep the deployed contract `contract_identifier` in memory, so that calls into it
+    ///  (e.g., from `eval_read_only` or `execute_contract`) skip deserializing it from the
+    ///  database. Loading a pinned contract is still charged as usual.
+    pub fn pin_contract(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+    ) -> Result<()> {
+        self.begin_read_only();
+        let contract = self.database.get_contract(contract_identifier);
+        self.roll_back();
+
+        self.pinned_contracts
+            .insert(contract_identifier.clone(), Rc::new(contract?));
+        Ok(())
+    }
+
+    /// Release a contract pinned by `pin_contract`. Returns whether it was pinned.
+    pub fn unpin_contract(&mut self, contract_identifier: &QualifiedContractIdentifier) -> bool {
+        self.pinned_contracts.remove(contract_identifier).is_some()
+    }
+
+    /// Make every call to `function_name` in `contract_identifier` return `result` without
+    ///  executing the function, e.g., to exercise a caller's handling of a failing
+    ///  `contract-call?` by mocking an `(err ...)` response. Mocked calls are neither
+    ///  type-checked nor charged, so this is only meant for testing.
+    #[cfg(feature = "developer-mode")]
+    pub fn mock_contract_call(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        function_name: ClarityName,
+        result: Value,
+    ) -> Result<()> {
+        if result.response_inner().is_none() {
+            return Err(
+                CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::type_of(&result))
+                    .into(),
+            );
+        }
+        self.mocked_calls
+            .insert((contract_identifier.clone(), function_name), result);
+        Ok(())
+    }
+
+    /// Remove a mock installed by `mock_contract_call`. Returns whether it was mocked.
+    #[cfg(feature = "developer-mode")]
+    pub fn unmock_contract_call(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        function_name: &ClarityName,
+    ) -> bool {
+        self.mocked_calls
+            .remove(&(contract_identifier.clone(), function_name.clone()))
+            .is_some()
+    }
+
+    #[cfg(feature = "developer-mode")]
+    fn get_mocked_call(
+        &self,
+        contract_identifier: &QualifiedContractIdentifier,
+        function_name: &ClarityName,
+    ) -> Option<Value> {
+        if self.mocked_calls.is_empty() {
+            return None;
+        }
+        self.mocked_calls
+            .get(&(contract_identifier.clone(), function_name.clone()))
+            .cloned()
+    }
+
+    fn load_contract(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+    ) -> Result<Rc<Contract>> {
+        match self.pinned_contracts.get(contract_identifier) {
+            Some(contract) => Ok(contract.clone()),
+            None => Ok(Rc::new(self.database.get_contract(contract_identifier)?)),
+        }
+    }
+
+    /// The ids of the expressions evaluated since coverage was enabled (or last taken).
+    ///  Recording continues.
+    pub fn take_coverage(&mut self) -> HashSet<u64> {
+        match self.coverage {
+            Some(ref mut coverage) => replace(coverage, HashSet::new()),
+            None => HashSet::new(),
+        }
+    }
+
+    pub fn record_coverage(&mut self, expression_id: u64) {
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.insert(expression_id);
+        }
+    }
+
+    /// Pause before executing `function` (see `execute_function_as_transaction`) to invoke
+    ///  the callback installed by `set_debug_callback`.
+    #[cfg(feature = "developer-mode")]
+    pub fn set_breakpoint(&mut self, function: FunctionIdentifier) {
+        self.breakpoints.insert(function);
+    }
+
+    /// Remove a breakpoint set by `set_breakpoint`. Returns whether it was set.
+    #[cfg(feature = "developer-mode")]
+    pub fn clear_breakpoint(&mut self, function: &FunctionIdentifier) -> bool {
+        self.breakpoints.remove(function)
+    }
+
+    /// Install the callback invoked when a breakpoint is hit. It is passed the environment
+    ///  the function is about to execute in, e.g., so a debugger can inspect its contract,
+    ///  callers, and the data it would read.
+    #[cfg(feature = "developer-mode")]
+    pub fn set_debug_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Environment) + 'static,
+    {
+        self.debug_callback = Some(Box::new(callback));
+    }
+
+    /// Install a callback invoked with `(from_contract, to_contract, function_name)` each
+    ///  time a function call crosses a contract boundary, e.g., for building call graphs.
+    pub fn set_contract_call_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&QualifiedContractIdentifier, &QualifiedContractIdentifier, &ClarityName)
+            + 'static,
+    {
+        self.contract_call_observer = Some(Box::new(observer));
+    }
+
+    fn observe_contract_call(
+        &mut self,
+        from: &QualifiedContractIdentifier,
+        to: &QualifiedContractIdentifier,
+        function_name: &ClarityName,
+    ) {
+        if from != to {
+            if let Some(ref mut observer) = self.contract_call_observer {
+                observer(from, to, function_name);
+            }
         }
     }
 
+    /// Record the database key of every read from here on, for analyzing access
+    ///  patterns (e.g., to tune a storage cache). Retrieve them with `take_read_log`.
+    #[cfg(feature = "developer-mode")]
+    pub fn enable_read_log(&mut self) {
+        self.database.enable_read_log();
+    }
+
+    #[cfg(feature = "developer-mode")]
+    pub fn take_read_log(&mut self) -> Vec<String> {
+        self.database.take_read_log()
+    }
+
     pub fn is_top_level(&self) -> bool {
         self.asset_maps.len() == 0
     }
 
+    /// Whether a write may go ahead. Fails with `WriteAttemptedInReadOnly` if the current
+    ///  context is read-only. When simulating writes (e.g., to preview what a function
+    ///  would do), the attempt is recorded as a warning instead and this returns false: the
+    ///  caller skips the write and returns the operation's no-op value, so later reads in
+    ///  the same call still see the stored data.
+    pub fn check_writable(
+        &mut self,
+        operation: &str,
+        contract_identifier: &QualifiedContractIdentifier,
+    ) -> Result<bool> {
+        if !self.is_read_only() {
+            return Ok(true);
+        }
+        if !self.simulate_writes {
+            return Err(CheckErrors::WriteAttemptedInReadOnly.into());
+        }
+        self.warnings.push(format!(
+            "{} in {} attempted in a read-only context; skipped",
+            operation, contract_identifier
+        ));
+        Ok(false)
+    }
+
+    /// The warnings recorded since simulating writes was enabled (or last taken).
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        replace(&mut self.warnings, Vec::new())
+    }
+
+    /// Whether the most recently completed top-level transaction wrote to the database
+    ///  or moved assets. False if it was rolled back. A transaction without side effects
+    ///  was effectively read-only, e.g., for waiving its fee.
+    pub fn had_side_effects(&self) -> bool {
+        self.had_side_effects
+    }
+
+    /// Credit `refund` to the current context (e.g., for storage a `map-delete` freed).
+    ///  Like the asset map, it is merged upward on commit and discarded on roll back.
+    pub fn add_refund(&mut self, refund: &ExecutionCost) -> Result<()> {
+        match self.cost_refunds.last_mut() {
+            Some(cost_refund) => cost_refund.add(refund).map_err(|e| e.into()),
+            None => Err(InterpreterError::InterpreterError(
+                "Refund credited outside of a context".into(),
+            )
+            .into()),
+        }
+    }
+
+    /// The cost refunded by the most recently completed top-level transaction. Zero if it
+    ///  was rolled back.
+    pub fn last_refund(&self) -> &ExecutionCost {
+        &self.last_refund
+    }
+
+    fn new_asset_map(&self) -> AssetMap {
+        let mut asset_map = AssetMap::new();
+        asset_map.set_reject_zero_transfers(self.reject_zero_transfers);
+        asset_map.set_reject_duplicate_nft_transfers(self.reject_duplicate_nft_transfers);
+        asset_map.max_entries = self.max_asset_map_entries;
+        asset_map.set_record_transfer_log(self.record_transfer_log);
+        asset_map
+    }
+
     fn get_asset_map(&mut self) -> &mut AssetMap {
         self.asset_maps
             .last_mut()
@@ -1297,13 +3567,19 @@ impl<'a> GlobalContext<'a> {
         contract_identifier: &QualifiedContractIdentifier,
         asset_name: &ClarityName,
         transfered: Value,
-    ) {
+    ) -> Result<()> {
         let asset_identifier = AssetIdentifier {
             contract_identifier: contract_identifier.clone(),
             asset_name: asset_name.clone(),
         };
+        // only developer builds keep a second copy, for the transfer heights.
+        #[cfg(feature = "developer-mode")]
+        let height_identifier = asset_identifier.clone();
         self.get_asset_map()
-            .add_asset_transfer(sender, asset_identifier, transfered)
+            .add_asset_transfer_checked(sender, asset_identifier, transfered)?;
+        #[cfg(feature = "developer-mode")]
+        self.record_transfer_height(sender, height_identifier);
+        Ok(())
     }
 
     pub fn log_token_transfer(
@@ -1317,8 +3593,24 @@ impl<'a> GlobalContext<'a> {
             contract_identifier: contract_identifier.clone(),
             asset_name: asset_name.clone(),
         };
+        #[cfg(feature = "developer-mode")]
+        let height_identifier = asset_identifier.clone();
+        self.get_asset_map()
+            .add_token_transfer(sender, asset_identifier, transfered)?;
+        #[cfg(feature = "developer-mode")]
+        self.record_transfer_height(sender, height_identifier);
+        Ok(())
+    }
+
+    #[cfg(feature = "developer-mode")]
+    fn record_transfer_height(
+        &mut self,
+        sender: &PrincipalData,
+        asset_identifier: AssetIdentifier,
+    ) {
+        let height = self.database.get_current_block_height();
         self.get_asset_map()
-            .add_token_transfer(sender, asset_identifier, transfered)
+            .record_transfer_height(sender, asset_identifier, height);
     }
 
     pub fn log_stx_transfer(&mut self, sender: &PrincipalData, transfered: u128) -> Result<()> {
@@ -1329,6 +3621,12 @@ impl<'a> GlobalContext<'a> {
         self.get_asset_map().add_stx_burn(sender, transfered)
     }
 
+    pub fn log_print(&mut self, value: Value) {
+        if let Some(prints) = self.print_sinks.last_mut() {
+            prints.push(value);
+        }
+    }
+
     pub fn execute<F, T>(&mut self, f: F) -> Result<T>
     where
         F: FnOnce(&mut Self) -> Result<T>,
@@ -1347,24 +3645,38 @@ impl<'a> GlobalContext<'a> {
         self.read_only.last().cloned().unwrap_or(false)
     }
 
-    pub fn begin(&mut self) {
-        self.asset_maps.push(AssetMap::new());
+    pub(crate) fn begin(&mut self) {
+        let asset_map = self.new_asset_map();
+        self.asset_maps.push(asset_map);
         self.event_batches.push(EventBatch::new());
+        self.print_sinks.push(Vec::new());
+        self.cost_refunds.push(ExecutionCost::zero());
+        self.push_context_id();
         self.database.begin();
         let read_only = self.is_read_only();
         self.read_only.push(read_only);
     }
 
-    pub fn begin_read_only(&mut self) {
-        self.asset_maps.push(AssetMap::new());
+    pub(crate) fn begin_read_only(&mut self) {
+        let asset_map = self.new_asset_map();
+        self.asset_maps.push(asset_map);
         self.event_batches.push(EventBatch::new());
+        self.print_sinks.push(Vec::new());
+        self.cost_refunds.push(ExecutionCost::zero());
+        self.push_context_id();
         self.database.begin();
         self.read_only.push(true);
     }
 
-    pub fn commit(&mut self) -> Result<(Option<AssetMap>, Option<EventBatch>)> {
+    fn push_context_id(&mut self) {
+        self.context_ids.push(self.next_context_id);
+        self.next_context_id += 1;
+    }
+
+    pub(crate) fn commit(&mut self) -> Result<CommitOutcome> {
         trace!("Calling commit");
         self.read_only.pop();
+        self.context_ids.pop();
         let asset_map = self
             .asset_maps
             .pop()
@@ -1373,56 +3685,217 @@ impl<'a> GlobalContext<'a> {
             .event_batches
             .pop()
             .expect("ERROR: Committed non-nested context.");
+        let mut prints = self
+            .print_sinks
+            .pop()
+            .expect("ERROR: Committed non-nested context.");
+        let cost_refund = self
+            .cost_refunds
+            .pop()
+            .expect("ERROR: Committed non-nested context.");
 
-        let out_map = match self.asset_maps.last_mut() {
-            Some(tail_back) => {
-                if let Err(e) = tail_back.commit_other(asset_map) {
-                    self.database.roll_back();
-                    return Err(e);
+        if let Some(max_bytes) = self.max_asset_map_bytes {
+            // an upper bound on the merged map: entries shared by both are counted twice.
+            let merged_bytes = asset_map.approximate_bytes()
+                + self
+                    .asset_maps
+                    .last()
+                    .map(|tail_map| tail_map.approximate_bytes())
+                    .unwrap_or(0);
+            if merged_bytes > max_bytes {
+                self.database.roll_back();
+                return Err(RuntimeErrorType::AssetMapTooLarge.into());
+            }
+        }
+
+        if self.asset_maps.is_empty() {
+            self.had_side_effects = !asset_map.is_empty() || self.database.has_pending_edits();
+            self.last_refund = cost_refund.clone();
+        }
+
+        let outcome = match (self.asset_maps.last_mut(), self.event_batches.last_mut()) {
+            (Some(tail_map), Some(tail_batch)) => {
+                // most nested contexts (e.g., read-only calls) never move assets,
+                //   so skip the merge entirely when there's nothing to merge.
+                if !asset_map.is_empty() {
+                    if let Err(e) = tail_map.commit_other(asset_map) {
+                        self.database.roll_back();
+                        return Err(e);
+                    }
                 }
-                None
+                tail_batch.events.append(&mut event_batch.events);
+                if let Some(tail_prints) = self.print_sinks.last_mut() {
+                    tail_prints.append(&mut prints);
+                }
+                if let Some(tail_refund) = self.cost_refunds.last_mut() {
+                    if let Err(e) = tail_refund.add(&cost_refund) {
+                        self.database.roll_back();
+                        return Err(e.into());
+                    }
+                }
+                CommitOutcome::MergedUpward
+            }
+            (None, None) => CommitOutcome::TopLevel(asset_map, event_batch, prints),
+            (Some(_), None) => {
+                self.database.roll_back();
+                return Err(InterpreterError::FailedToConstructEventBatch.into());
+            }
+            (None, Some(_)) => {
+                self.database.roll_back();
+                return Err(InterpreterError::FailedToConstructAssetTable.into());
             }
-            None => Some(asset_map),
         };
 
-        let out_batch = match self.event_batches.last_mut() {
-            Some(tail_back) => {
-                tail_back.events.append(&mut event_batch.events);
-                None
+        self.database.commit();
+        Ok(outcome)
+    }
+
+    pub(crate) fn commit_db_only(&mut self) -> Result<AssetMap> {
+        trace!("Calling commit_db_only");
+        self.read_only.pop();
+        self.context_ids.pop();
+        let asset_map = self
+            .asset_maps
+            .pop()
+            .expect("ERROR: Committed non-nested context.");
+        let mut event_batch = self
+            .event_batches
+            .pop()
+            .expect("ERROR: Committed non-nested context.");
+        let mut prints = self
+            .print_sinks
+            .pop()
+            .expect("ERROR: Committed non-nested context.");
+        let cost_refund = self
+            .cost_refunds
+            .pop()
+            .expect("ERROR: Committed non-nested context.");
+
+        if let Some(tail_batch) = self.event_batches.last_mut() {
+            tail_batch.events.append(&mut event_batch.events);
+        }
+        if let Some(tail_prints) = self.print_sinks.last_mut() {
+            tail_prints.append(&mut prints);
+        }
+
+        // the database writes are kept, so is any storage they freed.
+        match self.cost_refunds.last_mut() {
+            Some(tail_refund) => {
+                if let Err(e) = tail_refund.add(&cost_refund) {
+                    self.database.roll_back();
+                    return Err(e.into());
+                }
             }
-            None => Some(event_batch),
-        };
+            None => self.last_refund = cost_refund,
+        }
 
+        if self.asset_maps.is_empty() {
+            self.had_side_effects = self.database.has_pending_edits();
+        }
         self.database.commit();
-        Ok((out_map, out_batch))
+        Ok(asset_map)
     }
 
-    pub fn roll_back(&mut self) {
+    pub(crate) fn roll_back(&mut self) {
         let popped = self.asset_maps.pop();
         assert!(popped.is_some());
+        let popped = self.context_ids.pop();
+        assert!(popped.is_some());
         let popped = self.read_only.pop();
         assert!(popped.is_some());
         let popped = self.event_batches.pop();
         assert!(popped.is_some());
+        let popped = self.print_sinks.pop();
+        assert!(popped.is_some());
+        let popped = self.cost_refunds.pop();
+        assert!(popped.is_some());
 
+        if self.asset_maps.is_empty() {
+            self.had_side_effects = false;
+            self.last_refund = ExecutionCost::zero();
+        }
         self.database.roll_back();
     }
 
+    /// Like `begin`, but returns the token `commit_scope` or `roll_back_scope` needs to
+    ///  finish the new context.
+    pub fn begin_scope(&mut self) -> ContextScope {
+        self.begin();
+        self.innermost_scope()
+    }
+
+    /// Like `begin_read_only`, but returns the token that finishes the new context.
+    pub fn begin_read_only_scope(&mut self) -> ContextScope {
+        self.begin_read_only();
+        self.innermost_scope()
+    }
+
+    fn innermost_scope(&self) -> ContextScope {
+        ContextScope {
+            id: *self
+                .context_ids
+                .last()
+                .expect("ERROR: No context was opened for the scope."),
+        }
+    }
+
+    /// Commit the context opened with `scope`. Fails with
+    ///  `InterpreterError::MismatchedContextScope`, leaving every context open, if
+    ///  `scope` isn't the innermost open context (e.g., a nested context wasn't finished,
+    ///  or `scope`'s own context was already finished).
+    pub fn commit_scope(&mut self, scope: ContextScope) -> Result<CommitOutcome> {
+        self.check_scope(&scope)?;
+        self.commit()
+    }
+
+    /// Commit the database writes of the context opened with `scope`, but discard (and
+    ///  return) its asset map rather than merging it upward. This is an advanced
+    ///  primitive for embedders that do their own asset accounting: the returned map is
+    ///  _not_ checked for overflow or size limits against the enclosing context.
+    ///  Events and printed values are merged upward as in `commit`; in a top-level
+    ///  context they are dropped along with the asset map. Fails like `commit_scope` if
+    ///  `scope` isn't the innermost context.
+    pub fn commit_scope_db_only(&mut self, scope: ContextScope) -> Result<AssetMap> {
+        self.check_scope(&scope)?;
+        self.commit_db_only()
+    }
+
+    /// Roll back the context opened with `scope`. Fails like `commit_scope` if `scope`
+    ///  isn't the innermost context.
+    pub fn roll_back_scope(&mut self, scope: ContextScope) -> Result<()> {
+        self.check_scope(&scope)?;
+        self.roll_back();
+        Ok(())
+    }
+
+    fn check_scope(&self, scope: &ContextScope) -> Result<()> {
+        if self.context_ids.last() != Some(&scope.id) {
+            return Err(InterpreterError::MismatchedContextScope.into());
+        }
+        Ok(())
+    }
+
     pub fn handle_tx_result(&mut self, result: Result<Value>) -> Result<Value> {
         if let Ok(result) = result {
-            if let Value::Response(data) = result {
-                if data.committed {
+            let committed = match result.response_inner() {
+                Some((committed, _)) => committed,
+                None if !self.strict_response => {
                     self.commit()?;
-                } else {
-                    self.roll_back();
+                    return Value::okay(result);
                 }
-                Ok(Value::Response(data))
+                None => {
+                    return Err(CheckErrors::PublicFunctionMustReturnResponse(
+                        TypeSignature::type_of(&result),
+                    )
+                    .into())
+                }
+            };
+            if committed {
+                self.commit()?;
             } else {
-                Err(
-                    CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::type_of(&result))
-                        .into(),
-                )
+                self.roll_back();
             }
+            Ok(result)
         } else {
             self.roll_back();
             result
@@ -1458,14 +3931,142 @@ impl ContractContext {
         }
     }
 
+    /// A copy of this contract's constants and functions as the contract `name` of the
+    ///  same issuer, e.g., for test harnesses instantiating one parsed template several
+    ///  times. Storage (data vars, maps, and tokens) lives in the database under this
+    ///  contract, so its metadata and traits are not copied.
+    pub fn clone_with_name(&self, name: ContractName) -> ContractContext {
+        let contract_identifier =
+            QualifiedContractIdentifier::new(self.contract_identifier.issuer.clone(), name);
+        let context_name = contract_identifier.to_string();
+
+        let mut contract_context = ContractContext::new(contract_identifier);
+        contract_context.variables = self.variables.clone();
+        contract_context.functions = self
+            .functions
+            .iter()
+            .map(|(name, function)| (name.clone(), function.with_context_name(&context_name)))
+            .collect();
+        contract_context
+    }
+
     pub fn lookup_variable(&self, name: &str) -> Option<&Value> {
         self.variables.get(name)
     }
 
+    /// The calls each function's body makes, in the order they first appear, found by
+    ///  walking the bodies without evaluating them: calls to other functions of this
+    ///  contract, and `contract-call?`s.
+    pub fn call_graph(&self) -> HashMap<ClarityName, Vec<CallTarget>> {
+        self.functions
+            .iter()
+            .map(|(name, function)| {
+                let mut targets = Vec::new();
+                self.collect_call_targets(function.get_body(), &mut targets);
+                (name.clone(), targets)
+            })
+            .collect()
+    }
+
+    /// The private functions that no public or read-only function can reach through
+    /// `call_graph`, sorted by name. These can never be executed, and can be removed.
+    pub fn unreachable_functions(&self) -> Vec<&ClarityName> {
+        let call_graph = self.call_graph();
+
+        let mut reached = HashSet::new();
+        let mut to_visit: Vec<&ClarityName> = self
+            .functions
+            .iter()
+            .filter(|(_, function)| function.define_type != DefineType::Private)
+            .map(|(name, _)| name)
+            .collect();
+        while let Some(name) = to_visit.pop() {
+            if !reached.insert(name) {
+                continue;
+            }
+            if let Some(targets) = call_graph.get(name) {
+                for target in targets.iter() {
+                    if let CallTarget::Internal(ref callee) = target {
+                        to_visit.push(callee);
+                    }
+                }
+            }
+        }
+
+        let mut unreachable: Vec<&ClarityName> = self
+            .functions
+            .keys()
+            .filter(|name| !reached.contains(name))
+            .collect();
+        unreachable.sort();
+        unreachable
+    }
+
+    fn collect_call_targets(&self, expression: &SymbolicExpression, targets: &mut Vec<CallTarget>) {
+        let list = match expression.match_list() {
+            Some(list) => list,
+            None => return,
+        };
+
+        let target = match list.split_first() {
+            Some((head, args)) => match head.match_atom() {
+                Some(name) if name.as_str() == "contract-call?" && args.len() >= 2 => {
+                    match (&args[0].expr, args[1].match_atom()) {
+                        (
+                            SymbolicExpressionType::LiteralValue(Value::Principal(
+                                PrincipalData::Contract(ref contract_identifier),
+                            )),
+                            Some(function_name),
+                        ) => Some(CallTarget::Contract(
+                            contract_identifier.clone(),
+                            function_name.clone(),
+                        )),
+                        (SymbolicExpressionType::Atom(_), Some(function_name)) => {
+                            Some(CallTarget::Dynamic(function_name.clone()))
+                        }
+                        _ => None,
+                    }
+                }
+                Some(name) if self.functions.contains_key(name) => {
+                    Some(CallTarget::Internal(name.clone()))
+                }
+                // the higher-order functions take the function to apply by name.
+                Some(name) if ["map", "filter", "fold"].contains(&name.as_str()) => args
+                    .first()
+                    .and_then(|function| function.match_atom())
+                    .filter(|function_name| self.functions.contains_key(*function_name))
+                    .map(|function_name| CallTarget::Internal(function_name.clone())),
+                _ => None,
+            },
+            None => None,
+        };
+        if let Some(target) = target {
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+
+        for item in list.iter() {
+            self.collect_call_targets(item, targets);
+        }
+    }
+
+    /// Seed a constant directly, for test fixtures that want a contract in a specific
+    ///  state without running its initializer.
+    #[cfg(feature = "developer-mode")]
+    pub fn set_variable(&mut self, name: ClarityName, value: Value) {
+        self.variables.insert(name, value);
+    }
+
     pub fn lookup_function(&self, name: &str) -> Option<DefinedFunction> {
         self.functions.get(name).cloned()
     }
 
+    /// Borrows every function defined in this contract, in no particular order.
+    pub fn functions_iter(&self) -> impl Iterator<Item = (&ClarityName, &DefinedFunction)> {
+        self.functions.iter()
+    }
+
     pub fn lookup_trait_definition(
         &self,
         name: &str,
@@ -1540,12 +4141,59 @@ impl<'a> LocalContext<'a> {
     }
 }
 
+impl ParseCache {
+    fn new(capacity: usize) -> ParseCache {
+        ParseCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: VecDeque::new(),
+        }
+    }
+
+    fn get(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        program: &str,
+    ) -> Option<Vec<SymbolicExpression>> {
+        let key = (contract_identifier.clone(), program.to_string());
+        let parsed = self.entries.get(&key)?.clone();
+        if let Some(position) = self.recency.iter().position(|used| *used == key) {
+            self.recency.remove(position);
+        }
+        self.recency.push_back(key);
+        Some(parsed)
+    }
+
+    fn insert(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        program: &str,
+        parsed: Vec<SymbolicExpression>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (contract_identifier.clone(), program.to_string());
+        if !self.entries.contains_key(&key) {
+            if self.entries.len() >= self.capacity {
+                if let Some(evicted) = self.recency.pop_front() {
+                    self.entries.remove(&evicted);
+                }
+            }
+            self.recency.push_back(key.clone());
+        }
+        self.entries.insert(key, parsed);
+    }
+}
+
 impl CallStack {
     pub fn new() -> CallStack {
         CallStack {
             stack: Vec::new(),
+            contracts: Vec::new(),
             set: HashSet::new(),
             apply_depth: 0,
+            high_water_mark: 0,
         }
     }
 
@@ -1557,15 +4205,32 @@ impl CallStack {
         self.set.contains(function)
     }
 
-    pub fn insert(&mut self, function: &FunctionIdentifier, track: bool) {
+    pub fn insert(&mut self, function: &FunctionIdentifier, contract: &ContractName, track: bool) {
         self.stack.push(function.clone());
+        self.contracts.push(contract.clone());
         if track {
             self.set.insert(function.clone());
         }
+        self.update_high_water_mark();
     }
 
     pub fn incr_apply_depth(&mut self) {
         self.apply_depth += 1;
+        self.update_high_water_mark();
+    }
+
+    fn update_high_water_mark(&mut self) {
+        self.high_water_mark = self.high_water_mark.max(self.depth());
+    }
+
+    fn reset_high_water_mark(&mut self) {
+        self.high_water_mark = self.depth();
+    }
+
+    /// The deepest this call stack has been, as measured by `depth()`, so that limits
+    ///  like `MAX_CALL_STACK_DEPTH` can be tuned against real workloads.
+    pub fn max_depth_reached(&self) -> usize {
+        self.high_water_mark
     }
 
     pub fn decr_apply_depth(&mut self) {
@@ -1574,6 +4239,7 @@ impl CallStack {
 
     pub fn remove(&mut self, function: &FunctionIdentifier, tracked: bool) -> Result<()> {
         if let Some(removed) = self.stack.pop() {
+            self.contracts.pop();
             if removed != *function {
                 return Err(InterpreterError::InterpreterError(
                     "Tried to remove item from empty call stack.".to_string(),
@@ -1601,11 +4267,681 @@ impl CallStack {
     pub fn make_stack_trace(&self) -> StackTrace {
         Vec::new()
     }
+
+    /// The (contract, function) pair of each frame, outermost first, where the contract
+    ///  is the one active when the frame was pushed.
+    pub fn qualified_trace(&self) -> Vec<(ContractName, ClarityName)> {
+        self.contracts
+            .iter()
+            .zip(self.stack.iter())
+            .map(|(contract, function)| (contract.clone(), function.get_function_name()))
+            .collect()
+    }
+
+    /// Rebuild a call stack from a recorded stack trace, so that a replay harness
+    ///  can reproduce the exact nesting of a failed transaction. User functions are
+    ///  tracked for recursion checks, just as `apply` would have tracked them.
+    ///  A stack trace doesn't record the active contracts, so every frame is attributed
+    ///  to the transient contract.
+    #[cfg(feature = "developer-mode")]
+    pub fn from_trace(trace: &StackTrace) -> CallStack {
+        let transient = QualifiedContractIdentifier::transient().name;
+        let mut call_stack = CallStack::new();
+        for function in trace.iter() {
+            call_stack.insert(function, &transient, !function.is_native());
+        }
+        call_stack
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use vm::callables::{CallableType, DefineType, NativeHandle};
+
+    fn contract_principal(name: &str) -> PrincipalData {
+        PrincipalData::Contract(QualifiedContractIdentifier::local(name).unwrap())
+    }
+
+    fn asset_identifier(contract: &str, asset_name: &str) -> AssetIdentifier {
+        AssetIdentifier {
+            contract_identifier: QualifiedContractIdentifier::local(contract).unwrap(),
+            asset_name: asset_name.into(),
+        }
+    }
+
+    #[test]
+    fn test_extract_atom_values() {
+        let args = [
+            SymbolicExpression::atom_value(Value::Int(1)),
+            SymbolicExpression::atom("foo".into()),
+            SymbolicExpression::atom_value(Value::Int(2)),
+        ];
+
+        assert_eq!(
+            extract_atom_values(&args[..1]).unwrap(),
+            vec![Value::Int(1)]
+        );
+        assert_eq!(
+            extract_atom_values(&args).unwrap_err(),
+            CheckErrors::ExpectedLiteralArgument(1).into()
+        );
+    }
+
+    #[test]
+    fn test_senders_of_nft() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+
+        let t1 = asset_identifier("a", "a");
+
+        let mut am = AssetMap::new();
+        am.add_asset_transfer(&p1, t1.clone(), Value::Int(1));
+        am.add_asset_transfer(&p2, t1.clone(), Value::Int(2));
+
+        assert_eq!(am.senders_of_nft(&t1, &Value::Int(1)), vec![&p1]);
+        assert_eq!(am.senders_of_nft(&t1, &Value::Int(2)), vec![&p2]);
+        assert!(am.senders_of_nft(&t1, &Value::Int(3)).is_empty());
+    }
+
+    #[test]
+    fn test_reject_zero_transfers() {
+        let p1 = contract_principal("a");
+        let t1 = asset_identifier("a", "a");
+
+        let mut am = AssetMap::new();
+        am.add_token_transfer(&p1, t1.clone(), 0).unwrap();
+
+        am.set_reject_zero_transfers(true);
+        assert_eq!(
+            am.add_token_transfer(&p1, t1.clone(), 0).unwrap_err(),
+            RuntimeErrorType::ZeroTokenTransfer.into()
+        );
+        am.add_token_transfer(&p1, t1.clone(), 1).unwrap();
+        assert_eq!(am.get_fungible_tokens(&p1, &t1), Some(1));
+    }
+
+    #[test]
+    fn test_asset_map_canonical_bytes() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("tokens", "a");
+        let t2 = asset_identifier("tokens", "b");
+
+        let mut am1 = AssetMap::new();
+        am1.add_stx_transfer(&p1, 10).unwrap();
+        am1.add_stx_burn(&p2, 5).unwrap();
+        am1.add_token_transfer(&p1, t1.clone(), 20).unwrap();
+        am1.add_token_transfer(&p2, t1.clone(), 30).unwrap();
+        am1.add_asset_transfer(&p1, t2.clone(), Value::Int(1));
+        am1.add_asset_transfer(&p1, t2.clone(), Value::Int(2));
+
+        // the same entries, logged in a different order.
+        let mut am2 = AssetMap::new();
+        am2.add_asset_transfer(&p1, t2.clone(), Value::Int(1));
+        am2.add_token_transfer(&p2, t1.clone(), 30).unwrap();
+        am2.add_asset_transfer(&p1, t2.clone(), Value::Int(2));
+        am2.add_stx_burn(&p2, 5).unwrap();
+        am2.add_token_transfer(&p1, t1.clone(), 20).unwrap();
+        am2.add_stx_transfer(&p1, 10).unwrap();
+
+        let bytes = am1.to_canonical_bytes();
+        assert_eq!(bytes, am2.to_canonical_bytes());
+        assert_eq!(&bytes[0..2], &ASSET_MAP_FORMAT_VERSION.to_be_bytes());
+
+        let decoded = AssetMap::from_canonical_bytes(&bytes).unwrap();
+        assert_eq!(decoded.diff(&am1), "");
+        assert_eq!(decoded.to_canonical_bytes(), bytes);
+
+        let mut future = bytes.clone();
+        future[0..2].copy_from_slice(&(ASSET_MAP_FORMAT_VERSION + 1).to_be_bytes());
+        assert_eq!(
+            AssetMap::from_canonical_bytes(&future).unwrap_err(),
+            InterpreterError::UnsupportedAssetMapVersion(ASSET_MAP_FORMAT_VERSION + 1).into()
+        );
+        assert_eq!(
+            AssetMap::from_canonical_bytes(&bytes[0..bytes.len() - 1]).unwrap_err(),
+            InterpreterError::FailedToConstructAssetTable.into()
+        );
+
+        let empty = AssetMap::new().to_canonical_bytes();
+        assert!(AssetMap::from_canonical_bytes(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "developer-mode")]
+    fn test_asset_map_max_observed() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("tokens", "a");
+
+        let mut am = AssetMap::new();
+        assert_eq!(am.max_observed(&t1), 0);
+        am.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        am.add_token_transfer(&p1, t1.clone(), 15).unwrap();
+        am.add_token_transfer(&p2, t1.clone(), 20).unwrap();
+        am.add_stx_transfer(&p1, 7).unwrap();
+        assert_eq!(am.max_observed(&t1), 25);
+        assert_eq!(am.max_observed(&AssetIdentifier::STX()), 7);
+        assert_eq!(am.max_observed(&AssetIdentifier::STX_burned()), 0);
+
+        // merged totals are observed too.
+        let mut other = AssetMap::new();
+        other.add_token_transfer(&p2, t1.clone(), 30).unwrap();
+        am.commit_other(other).unwrap();
+        assert_eq!(am.max_observed(&t1), 50);
+
+        // a failed transfer doesn't count.
+        am.add_token_transfer(&p1, t1.clone(), u128::max_value())
+            .unwrap_err();
+        assert_eq!(am.max_observed(&t1), 50);
+    }
+
+    #[test]
+    fn test_asset_map_merged() {
+        let p1 = contract_principal("a");
+        let t1 = asset_identifier("tokens", "a");
+
+        let mut first = AssetMap::new();
+        first.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        first.add_stx_transfer(&p1, 5).unwrap();
+        let mut second = AssetMap::new();
+        second.add_token_transfer(&p1, t1.clone(), 20).unwrap();
+
+        let merged = first.merged(&second).unwrap();
+        assert_eq!(merged.get_fungible_tokens(&p1, &t1), Some(30));
+        assert_eq!(merged.get_stx(&p1), Some(5));
+        // neither input is changed.
+        assert_eq!(first.get_fungible_tokens(&p1, &t1), Some(10));
+        assert_eq!(second.get_fungible_tokens(&p1, &t1), Some(20));
+
+        let mut overflowing = AssetMap::new();
+        overflowing
+            .add_token_transfer(&p1, t1.clone(), u128::max_value())
+            .unwrap();
+        assert_eq!(
+            merged.merged(&overflowing).unwrap_err(),
+            RuntimeErrorType::ArithmeticOverflow.into()
+        );
+    }
+
+    #[test]
+    fn test_asset_map_nft_count() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let n1 = asset_identifier("tokens", "n");
+
+        let mut am = AssetMap::new();
+        am.add_asset_transfer(&p1, n1.clone(), Value::Int(1));
+        am.add_asset_transfer(&p1, n1.clone(), Value::Int(2));
+
+        assert_eq!(am.nft_count_for(&p1, &n1), 2);
+        assert_eq!(am.nft_count_for(&p2, &n1), 0);
+    }
+
+    #[test]
+    fn test_asset_map_ledger_entries() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("tokens", "a");
+        let n1 = asset_identifier("tokens", "n");
+
+        let mut am = AssetMap::new();
+        am.add_asset_transfer(&p2, n1.clone(), Value::Int(2));
+        am.add_asset_transfer(&p2, n1.clone(), Value::Int(1));
+        am.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        am.add_stx_burn(&p2, 4).unwrap();
+
+        let entry = |debit: &PrincipalData, asset: &AssetIdentifier, amount| LedgerEntry {
+            debit: debit.clone(),
+            asset: asset.clone(),
+            amount,
+        };
+        assert_eq!(
+            am.to_ledger_entries(),
+            vec![
+                entry(&p1, &t1, LedgerAmount::Amount(10)),
+                entry(&p2, &AssetIdentifier::STX_burned(), LedgerAmount::Amount(4)),
+                entry(&p2, &n1, LedgerAmount::Asset(Value::Int(2))),
+                entry(&p2, &n1, LedgerAmount::Asset(Value::Int(1))),
+            ]
+        );
+        assert!(AssetMap::new().to_ledger_entries().is_empty());
+    }
+
+    #[test]
+    fn test_asset_map_transfer_log() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("tokens", "a");
+        let n1 = asset_identifier("tokens", "n");
+
+        let mut am = AssetMap::new();
+        am.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        assert_eq!(am.transfer_log(), None);
+
+        am.set_record_transfer_log(true);
+        am.add_stx_transfer(&p2, 5).unwrap();
+        am.add_token_transfer(&p1, t1.clone(), 3).unwrap();
+
+        let mut nested = AssetMap::new();
+        nested.set_record_transfer_log(true);
+        nested.add_asset_transfer(&p2, n1.clone(), Value::Int(1));
+        nested.add_stx_burn(&p1, 2).unwrap();
+        am.commit_other(nested).unwrap();
+        am.add_token_transfer(&p1, t1.clone(), 4).unwrap();
+
+        let event =
+            |index, sender: &PrincipalData, asset: &AssetIdentifier, amount| TransferEvent {
+                index,
+                sender: sender.clone(),
+                asset: asset.clone(),
+                amount,
+            };
+        assert_eq!(
+            am.transfer_log().unwrap(),
+            &[
+                event(0, &p2, &AssetIdentifier::STX(), LedgerAmount::Amount(5)),
+                event(1, &p1, &t1, LedgerAmount::Amount(3)),
+                event(2, &p2, &n1, LedgerAmount::Asset(Value::Int(1))),
+                event(
+                    3,
+                    &p1,
+                    &AssetIdentifier::STX_burned(),
+                    LedgerAmount::Amount(2)
+                ),
+                event(4, &p1, &t1, LedgerAmount::Amount(4)),
+            ][..]
+        );
+        // the totals are unaffected by the log.
+        assert_eq!(am.get_fungible_tokens(&p1, &t1), Some(17));
+
+        am.set_record_transfer_log(false);
+        assert_eq!(am.transfer_log(), None);
+    }
+
+    #[test]
+    fn test_asset_map_delta_since() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("tokens", "a");
+        let n1 = asset_identifier("tokens", "n");
+
+        let mut am = AssetMap::new();
+        am.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        am.add_stx_transfer(&p1, 5).unwrap();
+        am.add_asset_transfer(&p1, n1.clone(), Value::Int(1));
+        let checkpoint = am.clone();
+
+        am.add_token_transfer(&p1, t1.clone(), 7).unwrap();
+        am.add_token_transfer(&p2, t1.clone(), 3).unwrap();
+        am.add_stx_burn(&p2, 4).unwrap();
+        am.add_asset_transfer(&p1, n1.clone(), Value::Int(2));
+
+        let delta = am.delta_since(&checkpoint);
+        assert_eq!(delta.get_fungible_tokens(&p1, &t1), Some(7));
+        assert_eq!(delta.get_fungible_tokens(&p2, &t1), Some(3));
+        assert_eq!(delta.get_stx(&p1), None);
+        assert_eq!(delta.get_stx_burned(&p2), Some(4));
+        assert_eq!(
+            delta.get_nonfungible_tokens(&p1, &n1),
+            Some(&vec![Value::Int(2)])
+        );
+
+        assert_eq!(am.delta_since(&am).entry_count(), 0);
+        assert_eq!(
+            am.delta_since(&AssetMap::new()).canonical_entries(),
+            am.canonical_entries()
+        );
+    }
+
+    #[test]
+    fn test_asset_map_sorted_iteration() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("tokens", "a");
+        let t2 = asset_identifier("tokens", "b");
+
+        let mut am = AssetMap::new();
+        am.add_token_transfer(&p2, t1.clone(), 3).unwrap();
+        am.add_token_transfer(&p1, t2.clone(), 2).unwrap();
+        am.add_token_transfer(&p1, t1.clone(), 1).unwrap();
+        am.add_asset_transfer(&p2, t2.clone(), Value::Int(2));
+        am.add_asset_transfer(&p1, t2.clone(), Value::Int(1));
+
+        let tokens: Vec<_> = am.iter_tokens_sorted().collect();
+        assert_eq!(tokens, vec![(&p1, &t1, 1), (&p1, &t2, 2), (&p2, &t1, 3)]);
+
+        let assets: Vec<_> = am.iter_assets_sorted().collect();
+        assert_eq!(
+            assets,
+            vec![
+                (&p1, &t2, &[Value::Int(1)][..]),
+                (&p2, &t2, &[Value::Int(2)][..])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_asset_map_bounded() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("tokens", "a");
+        let t2 = asset_identifier("tokens", "b");
+
+        let mut am = AssetMap::bounded(2);
+        am.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        am.add_asset_transfer_checked(&p1, t2.clone(), Value::Int(1))
+            .unwrap();
+        // adding to an existing balance doesn't take up another entry.
+        am.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        am.add_stx_transfer(&p1, 10).unwrap();
+
+        assert_eq!(
+            am.add_token_transfer(&p2, t1.clone(), 10).unwrap_err(),
+            RuntimeErrorType::AssetMapFull.into()
+        );
+        assert_eq!(
+            am.add_asset_transfer_checked(&p1, t2.clone(), Value::Int(2))
+                .unwrap_err(),
+            RuntimeErrorType::AssetMapFull.into()
+        );
+
+        // merges are bounded too, and leave the map unchanged on failure.
+        let mut other = AssetMap::new();
+        other.add_token_transfer(&p1, t1.clone(), 5).unwrap();
+        other.add_token_transfer(&p2, t2.clone(), 5).unwrap();
+        assert_eq!(
+            am.commit_other(other).unwrap_err(),
+            RuntimeErrorType::AssetMapFull.into()
+        );
+        assert_eq!(am.get_fungible_tokens(&p1, &t1), Some(20));
+
+        let mut other = AssetMap::new();
+        other.add_token_transfer(&p1, t1.clone(), 5).unwrap();
+        am.commit_other(other).unwrap();
+        assert_eq!(am.get_fungible_tokens(&p1, &t1), Some(25));
+    }
+
+    #[test]
+    fn test_parse_cache() {
+        let contract = QualifiedContractIdentifier::local("contract").unwrap();
+        let other = QualifiedContractIdentifier::local("other").unwrap();
+        let parse = |program: &str| {
+            ast::build_ast(&contract, program, &mut ())
+                .unwrap()
+                .expressions
+        };
+
+        let mut cache = ParseCache::new(2);
+        assert!(cache.get(&contract, "(+ 1 2)").is_none());
+
+        cache.insert(&contract, "(+ 1 2)", parse("(+ 1 2)"));
+        cache.insert(&contract, "(+ 3 4)", parse("(+ 3 4)"));
+        assert_eq!(cache.get(&contract, "(+ 1 2)"), Some(parse("(+ 1 2)")));
+        // the same program is cached separately per contract.
+        assert!(cache.get(&other, "(+ 1 2)").is_none());
+
+        // (+ 3 4) is now the least recently used.
+        cache.insert(&contract, "(+ 5 6)", parse("(+ 5 6)"));
+        assert!(cache.get(&contract, "(+ 3 4)").is_none());
+        assert!(cache.get(&contract, "(+ 1 2)").is_some());
+        assert!(cache.get(&contract, "(+ 5 6)").is_some());
+
+        let mut disabled = ParseCache::new(0);
+        disabled.insert(&contract, "(+ 1 2)", parse("(+ 1 2)"));
+        assert!(disabled.get(&contract, "(+ 1 2)").is_none());
+    }
+
+    #[test]
+    fn test_call_stack_qualified_trace() {
+        fn native_add(_: Value, _: Value) -> Result<Value> {
+            Ok(Value::Int(0))
+        }
+
+        let callee = DefinedFunction::new(
+            vec![],
+            SymbolicExpression::atom_value(Value::Int(1)),
+            DefineType::Public,
+            &"pong".into(),
+            &QualifiedContractIdentifier::local("callee")
+                .unwrap()
+                .to_string(),
+        );
+        let native = CallableType::NativeFunction(
+            "+",
+            NativeHandle::DoubleArg(&native_add),
+            ClarityCostFunction::Add,
+        );
+
+        let mut call_stack = CallStack::new();
+        call_stack.insert(&callee.get_identifier(), &"caller".into(), true);
+        call_stack.insert(&native.get_identifier(), &"callee".into(), false);
+
+        assert_eq!(
+            call_stack.qualified_trace(),
+            vec![
+                ("caller".into(), "pong".into()),
+                ("callee".into(), "+".into())
+            ]
+        );
+
+        call_stack.remove(&native.get_identifier(), false).unwrap();
+        assert_eq!(
+            call_stack.qualified_trace(),
+            vec![("caller".into(), "pong".into())]
+        );
+
+        call_stack.incr_apply_depth();
+        call_stack.decr_apply_depth();
+        call_stack.remove(&callee.get_identifier(), true).unwrap();
+        assert_eq!(call_stack.depth(), 0);
+        assert_eq!(call_stack.max_depth_reached(), 2);
+
+        call_stack.reset_high_water_mark();
+        assert_eq!(call_stack.max_depth_reached(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "developer-mode")]
+    fn test_call_stack_from_trace() {
+        let function = DefinedFunction::new(
+            vec![],
+            SymbolicExpression::atom_value(Value::Int(1)),
+            DefineType::Private,
+            &"foo".into(),
+            "contract",
+        );
+        let identifier = function.get_identifier();
+
+        let mut call_stack = CallStack::new();
+        call_stack.insert(&identifier, &"contract".into(), true);
+        let trace = call_stack.make_stack_trace();
+
+        let replayed = CallStack::from_trace(&trace);
+        assert_eq!(replayed.depth(), 1);
+        assert!(replayed.contains(&identifier));
+        assert_eq!(replayed.make_stack_trace(), trace);
+    }
+
+    #[test]
+    fn test_reject_duplicate_nft_transfers() {
+        let p1 = contract_principal("a");
+        let t1 = asset_identifier("a", "a");
+
+        let mut am = AssetMap::new();
+        am.add_asset_transfer_checked(&p1, t1.clone(), Value::Int(1))
+            .unwrap();
+        am.add_asset_transfer_checked(&p1, t1.clone(), Value::Int(1))
+            .unwrap();
+
+        let mut am1 = AssetMap::new();
+        am1.set_reject_duplicate_nft_transfers(true);
+        am1.add_asset_transfer_checked(&p1, t1.clone(), Value::Int(1))
+            .unwrap();
+        assert_eq!(
+            am1.add_asset_transfer_checked(&p1, t1.clone(), Value::Int(1))
+                .unwrap_err(),
+            RuntimeErrorType::DuplicateNftTransfer.into()
+        );
+
+        // duplicates are also caught when merging a nested map
+        let mut am2 = AssetMap::new();
+        am2.add_asset_transfer_checked(&p1, t1.clone(), Value::Int(1))
+            .unwrap();
+        assert_eq!(
+            am1.commit_other(am2).unwrap_err(),
+            RuntimeErrorType::DuplicateNftTransfer.into()
+        );
+        assert_eq!(
+            am1.get_nonfungible_tokens(&p1, &t1),
+            Some(&vec![Value::Int(1)])
+        );
+    }
+
+    #[test]
+    fn test_asset_map_is_empty() {
+        let p1 = contract_principal("a");
+        let t1 = asset_identifier("a", "a");
+
+        let mut am = AssetMap::new();
+        assert!(am.is_empty());
+        am.add_asset_transfer(&p1, t1.clone(), Value::Int(1));
+        assert!(!am.is_empty());
+
+        let mut am = AssetMap::new();
+        am.add_stx_burn(&p1, 1).unwrap();
+        assert!(!am.is_empty());
+    }
+
+    #[test]
+    fn test_commit_other_tagged() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("a", "a");
+
+        let mut block_map = AssetMap::new();
+
+        let mut tx_map = AssetMap::new();
+        tx_map.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        tx_map.add_stx_transfer(&p2, 5).unwrap();
+        block_map.commit_other_tagged(tx_map, 0).unwrap();
+
+        let mut tx_map = AssetMap::new();
+        tx_map.add_token_transfer(&p1, t1.clone(), 20).unwrap();
+        block_map.commit_other_tagged(tx_map, 1).unwrap();
+
+        assert_eq!(block_map.get_fungible_tokens(&p1, &t1), Some(30));
+        assert_eq!(block_map.contributors(&p1, &t1), &[0, 1]);
+        assert_eq!(block_map.contributors(&p2, &AssetIdentifier::STX()), &[0]);
+        assert!(block_map.contributors(&p2, &t1).is_empty());
+    }
+
+    #[test]
+    fn test_asset_map_diff() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("a", "a");
+        let t2 = asset_identifier("a", "b");
+
+        let mut expected = AssetMap::new();
+        expected.add_token_transfer(&p1, t1.clone(), 10).unwrap();
+        expected.add_stx_transfer(&p2, 5).unwrap();
+
+        let mut actual = AssetMap::new();
+        actual.add_token_transfer(&p1, t1.clone(), 11).unwrap();
+        actual.add_asset_transfer(&p2, t2.clone(), Value::Int(1));
+
+        assert_eq!(expected.diff(&expected.clone()), "");
+        assert_eq!(
+            actual.diff(&expected),
+            format!(
+                "~ {} {}: expected 10, got 11\n- {} {}: 5\n+ {} {}: [1]\n",
+                p1,
+                t1,
+                p2,
+                AssetIdentifier::STX(),
+                p2,
+                t2
+            )
+        );
+    }
+
+    #[test]
+    fn test_asset_map_display_with() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+
+        let mut am = AssetMap::new();
+        am.add_stx_transfer(&p1, 10).unwrap();
+        am.add_stx_burn(&p2, 5).unwrap();
+
+        let alice = p1.clone();
+        let resolver = move |principal: &PrincipalData| {
+            if *principal == alice {
+                Some("alice".to_string())
+            } else {
+                None
+            }
+        };
+        assert_eq!(
+            am.display_with(&resolver).to_string(),
+            format!("[alice spent 10 microSTX\n{} burned 5 microSTX\n]", p2)
+        );
+        assert_eq!(
+            am.to_string(),
+            format!("[{} spent 10 microSTX\n{} burned 5 microSTX\n]", p1, p2)
+        );
+    }
+
+    #[test]
+    fn test_asset_identifiers() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+        let t1 = asset_identifier("a", "a");
+        let t2 = asset_identifier("a", "b");
+
+        let mut am = AssetMap::new();
+        am.add_token_transfer(&p1, t1.clone(), 1).unwrap();
+        am.add_token_transfer(&p2, t1.clone(), 1).unwrap();
+        am.add_asset_transfer(&p2, t2.clone(), Value::Int(1));
+        am.add_stx_transfer(&p1, 1).unwrap();
+
+        let expected: HashSet<&AssetIdentifier> = vec![&t1, &t2].into_iter().collect();
+        assert_eq!(am.asset_identifiers(), expected);
+    }
+
+    #[test]
+    fn test_asset_map_entry_accessors() {
+        assert_eq!(AssetMapEntry::STX(1).as_amount(), Some(1));
+        assert_eq!(AssetMapEntry::Burn(2).as_amount(), Some(2));
+        assert_eq!(AssetMapEntry::Token(3).as_amount(), Some(3));
+        assert_eq!(AssetMapEntry::Token(3).nft_count(), None);
+
+        let nfts = AssetMapEntry::Asset(vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!(nfts.as_amount(), None);
+        assert_eq!(nfts.nft_count(), Some(2));
+    }
+
+    #[test]
+    fn test_asset_map_approximate_bytes() {
+        let p1 = contract_principal("a");
+        let t1 = asset_identifier("a", "a");
+
+        let mut am = AssetMap::new();
+        assert_eq!(am.approximate_bytes(), 0);
+
+        am.add_stx_transfer(&p1, 1).unwrap();
+        assert_eq!(am.approximate_bytes(), 22 + 16);
+
+        am.add_asset_transfer(&p1, t1.clone(), Value::Int(1));
+        let one_nft = am.approximate_bytes();
+        am.add_asset_transfer(&p1, t1.clone(), Value::Int(2));
+        assert_eq!(
+            am.approximate_bytes(),
+            one_nft + Value::Int(2).size() as usize
+        );
+    }
 
     #[test]
     fn test_asset_map_abort() {
@@ -1641,6 +4977,29 @@ mod test {
         assert_eq!(table[&p1][&t1], AssetMapEntry::Token(1));
     }
 
+    #[test]
+    fn test_asset_map_stx_abort() {
+        let p1 = contract_principal("a");
+        let p2 = contract_principal("b");
+
+        let t1 = asset_identifier("a", "a");
+
+        let mut am1 = AssetMap::new();
+        let mut am2 = AssetMap::new();
+
+        am1.add_stx_transfer(&p1, 1).unwrap();
+        am1.add_stx_transfer(&p2, u128::max_value()).unwrap();
+        am2.add_stx_transfer(&p1, 1).unwrap();
+        am2.add_stx_transfer(&p2, 1).unwrap();
+        am2.add_token_transfer(&p1, t1.clone(), 1).unwrap();
+
+        am1.commit_other(am2).unwrap_err();
+
+        assert_eq!(am1.get_stx(&p1), Some(1));
+        assert_eq!(am1.get_stx(&p2), Some(u128::max_value()));
+        assert_eq!(am1.get_fungible_tokens(&p1, &t1), None);
+    }
+
     #[test]
     fn test_asset_map_combinations() {
         let a_contract_id = QualifiedContractIdentifier::local("a").unwrap();
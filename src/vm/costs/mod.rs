@@ -1061,6 +1061,19 @@ impl ExecutionCost {
             || self.read_length > other.read_length
     }
 
+    /// Returns this cost net of `refund`. Refunds are capped at half of the cost in
+    ///  each dimension, so a transaction can never be refunded more than it spent.
+    pub fn with_refund(&self, refund: &ExecutionCost) -> ExecutionCost {
+        let net = |cost: u64, refund: u64| cost - cmp::min(refund, cost / 2);
+        Self {
+            runtime: net(self.runtime, refund.runtime),
+            write_length: net(self.write_length, refund.write_length),
+            write_count: net(self.write_count, refund.write_count),
+            read_count: net(self.read_count, refund.read_count),
+            read_length: net(self.read_length, refund.read_length),
+        }
+    }
+
     pub fn max_cost(first: ExecutionCost, second: ExecutionCost) -> ExecutionCost {
         Self {
             runtime: first.runtime.max(second.runtime),
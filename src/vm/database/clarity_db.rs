@@ -15,7 +15,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use rusqlite::OptionalExtension;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::convert::{TryFrom, TryInto};
 
 use vm::contracts::Contract;
@@ -84,6 +84,9 @@ pub struct ClarityDatabase<'a> {
     pub store: RollbackWrapper<'a>,
     headers_db: &'a dyn HeadersDB,
     burn_state_db: &'a dyn BurnStateDB,
+    // keys read through this database, recorded only once enabled.
+    #[cfg(feature = "developer-mode")]
+    read_log: Option<Vec<String>>,
 }
 
 pub trait HeadersDB {
@@ -341,6 +344,8 @@ impl<'a> ClarityDatabase<'a> {
             store: RollbackWrapper::new(store),
             headers_db,
             burn_state_db,
+            #[cfg(feature = "developer-mode")]
+            read_log: None,
         }
     }
 
@@ -353,6 +358,8 @@ impl<'a> ClarityDatabase<'a> {
             store,
             headers_db,
             burn_state_db,
+            #[cfg(feature = "developer-mode")]
+            read_log: None,
         }
     }
 
@@ -377,6 +384,11 @@ impl<'a> ClarityDatabase<'a> {
         self.store.rollback();
     }
 
+    /// True if the current key-value wrapper layer has written anything
+    pub fn has_pending_edits(&self) -> bool {
+        self.store.has_pending_edits()
+    }
+
     pub fn set_block_hash(
         &mut self,
         bhh: StacksBlockId,
@@ -393,10 +405,12 @@ impl<'a> ClarityDatabase<'a> {
     where
         T: ClarityDeserializable<T>,
     {
+        self.log_read(key);
         self.store.get::<T>(key)
     }
 
     pub fn get_value(&mut self, key: &str, expected: &TypeSignature) -> Option<Value> {
+        self.log_read(key);
         self.store.get_value(key, expected)
     }
 
@@ -404,9 +418,37 @@ impl<'a> ClarityDatabase<'a> {
     where
         T: ClarityDeserializable<T>,
     {
+        self.log_read(key);
         self.store.get_with_proof(key)
     }
 
+    /// Start recording the keys of every subsequent read, in order.
+    #[cfg(feature = "developer-mode")]
+    pub fn enable_read_log(&mut self) {
+        if self.read_log.is_none() {
+            self.read_log = Some(vec![]);
+        }
+    }
+
+    /// The keys read since the log was enabled (or last taken). Recording continues.
+    #[cfg(feature = "developer-mode")]
+    pub fn take_read_log(&mut self) -> Vec<String> {
+        match self.read_log {
+            Some(ref mut read_log) => std::mem::replace(read_log, vec![]),
+            None => vec![],
+        }
+    }
+
+    #[cfg(feature = "developer-mode")]
+    fn log_read(&mut self, key: &str) {
+        if let Some(ref mut read_log) = self.read_log {
+            read_log.push(key.to_string());
+        }
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    fn log_read(&mut self, _key: &str) {}
+
     pub fn make_key_for_trip(
         contract_identifier: &QualifiedContractIdentifier,
         data: StoreType,
@@ -461,6 +503,37 @@ impl<'a> ClarityDatabase<'a> {
             .flatten()
     }
 
+    /// Store metadata for tooling (e.g., a source hash or compiler version) alongside a
+    ///  contract. Like the contract source, it is not part of the consensus data.
+    pub fn insert_contract_tool_metadata(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+        metadata: &HashMap<String, String>,
+    ) {
+        let key = ClarityDatabase::make_metadata_key(StoreType::Contract, "contract-tool-metadata");
+        // sort the entries, so that the stored metadata is deterministic.
+        let metadata: BTreeMap<_, _> = metadata.iter().collect();
+        let serialized =
+            serde_json::to_string(&metadata).expect("Failed to serialize contract metadata");
+        self.insert_metadata(contract_identifier, &key, &serialized);
+    }
+
+    /// The metadata stored by `insert_contract_tool_metadata`, or an empty map if there
+    ///  is none.
+    pub fn get_contract_tool_metadata(
+        &mut self,
+        contract_identifier: &QualifiedContractIdentifier,
+    ) -> Result<HashMap<String, String>> {
+        let key = ClarityDatabase::make_metadata_key(StoreType::Contract, "contract-tool-metadata");
+        let serialized: Option<String> = self.fetch_metadata(contract_identifier, &key)?;
+        Ok(match serialized {
+            Some(serialized) => {
+                serde_json::from_str(&serialized).expect("Failed to deserialize contract metadata")
+            }
+            None => HashMap::new(),
+        })
+    }
+
     pub fn set_metadata(
         &mut self,
         contract_identifier: &QualifiedContractIdentifier,
@@ -236,6 +236,15 @@ impl<'a> RollbackWrapper<'a> {
         self.stack.len()
     }
 
+    /// True if the current context (including the contexts committed into it) has
+    ///  written any data or metadata.
+    pub fn has_pending_edits(&self) -> bool {
+        match self.stack.last() {
+            Some(context) => !context.edits.is_empty() || !context.metadata_edits.is_empty(),
+            None => false,
+        }
+    }
+
     pub fn commit(&mut self) {
         let mut last_item = self
             .stack
@@ -63,6 +63,9 @@ pub enum InterpreterError {
     InsufficientBalance,
     CostContractLoadFailure,
     DBError(IncomparableError<DatabaseError>),
+    ASTRetentionDisabled,
+    UnsupportedAssetMapVersion(u16),
+    MismatchedContextScope,
 }
 
 /// RuntimeErrors are errors that smart contracts are expected
@@ -96,6 +99,13 @@ pub enum RuntimeErrorType {
     UnknownBlockHeaderHash(BlockHeaderHash),
     BadBlockHash(Vec<u8>),
     UnwrapFailure,
+    TooManyDefinitions,
+    ZeroTokenTransfer,
+    DuplicateNftTransfer,
+    Aborted,
+    AssetMapTooLarge,
+    AssetMapFull,
+    MultipleExpressions,
 }
 
 #[derive(Debug, PartialEq)]
@@ -124,6 +134,48 @@ impl PartialEq<Error> for Error {
     }
 }
 
+impl Error {
+    /// Whether re-executing the transaction that raised this error is certain to raise it
+    ///  again, so that, e.g., a mempool can drop the transaction instead of retrying it.
+    ///  Only infrastructure failures (see `InterpreterError::is_permanent`) and aborts are
+    ///  retryable.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Error::Unchecked(_) => true,
+            Error::Interpreter(err) => err.is_permanent(),
+            Error::Runtime(err, _) => err.is_permanent(),
+            Error::ShortReturn(_) => true,
+        }
+    }
+}
+
+impl InterpreterError {
+    /// Database, storage, and cost-contract failures are retryable. Every other
+    ///  interpreter error stems from the transaction itself and is permanent.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            InterpreterError::SqliteError(_)
+            | InterpreterError::MarfFailure(_)
+            | InterpreterError::DBError(_)
+            | InterpreterError::BadFileName
+            | InterpreterError::FailedToCreateDataDirectory
+            | InterpreterError::CostContractLoadFailure => false,
+            _ => true,
+        }
+    }
+}
+
+impl RuntimeErrorType {
+    /// Runtime errors are deterministic, and so permanent, except for `Aborted`, which is
+    ///  raised by the embedder rather than by the transaction.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            RuntimeErrorType::Aborted => false,
+            _ => true,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -250,4 +302,23 @@ _native_:native_div
                 != Error::Interpreter(InterpreterError::InterpreterError("".to_string()))
         );
     }
+
+    #[test]
+    fn permanence() {
+        assert!(execute("(/ 10 0)").unwrap_err().is_permanent());
+        assert!(Error::from(RuntimeErrorType::ArithmeticOverflow).is_permanent());
+        assert!(Error::from(CheckErrors::TypeError(
+            TypeSignature::IntType,
+            TypeSignature::UIntType
+        ))
+        .is_permanent());
+        assert!(Error::from(InterpreterError::BadSender(Value::Int(1))).is_permanent());
+
+        assert!(!Error::from(RuntimeErrorType::Aborted).is_permanent());
+        assert!(!Error::from(InterpreterError::CostContractLoadFailure).is_permanent());
+        assert!(!Error::from(InterpreterError::DBError(IncomparableError {
+            err: DatabaseError::NotFoundError
+        }))
+        .is_permanent());
+    }
 }
@@ -405,7 +405,7 @@ pub fn special_transfer_asset(
             &env.contract_context.contract_identifier,
             asset_name,
             asset.clone(),
-        );
+        )?;
 
         let asset_identifier = AssetIdentifier {
             contract_identifier: env.contract_context.contract_identifier.clone(),
@@ -749,7 +749,7 @@ pub fn special_burn_asset(
             &env.contract_context.contract_identifier,
             asset_name,
             asset.clone(),
-        );
+        )?;
 
         let asset_identifier = AssetIdentifier {
             contract_identifier: env.contract_context.contract_identifier.clone(),
@@ -22,7 +22,8 @@ use vm::functions::tuples;
 use chainstate::stacks::StacksBlockId;
 use vm::callables::DefineType;
 use vm::costs::{
-    constants as cost_constants, cost_functions, runtime_cost, CostTracker, MemoryConsumer,
+    constants as cost_constants, cost_functions, runtime_cost, CostTracker, ExecutionCost,
+    MemoryConsumer,
 };
 use vm::errors::{
     check_argument_count, check_arguments_at_least, CheckErrors, InterpreterError,
@@ -152,9 +153,7 @@ pub fn special_contract_call(
         _ => return Err(CheckErrors::ContractCallExpectName.into()),
     };
 
-    let contract_principal = Value::Principal(PrincipalData::Contract(
-        env.contract_context.contract_identifier.clone(),
-    ));
+    let contract_principal = Value::Principal(env.contract_principal());
 
     let mut nested_env = env.nest_with_caller(contract_principal);
     let result = if nested_env.short_circuit_contract_call(
@@ -216,9 +215,9 @@ pub fn special_set_variable(
     env: &mut Environment,
     context: &LocalContext,
 ) -> Result<Value> {
-    if env.global_context.is_read_only() {
-        return Err(CheckErrors::WriteAttemptedInReadOnly.into());
-    }
+    let writable = env
+        .global_context
+        .check_writable("var-set", &env.contract_context.contract_identifier)?;
 
     check_argument_count(2, args)?;
 
@@ -242,6 +241,10 @@ pub fn special_set_variable(
 
     env.add_memory(value.get_memory_use())?;
 
+    if !writable {
+        return Ok(Value::Bool(true));
+    }
+
     env.global_context
         .database
         .set_variable(contract, var_name, value, data_types)
@@ -309,9 +312,9 @@ pub fn special_set_entry(
     env: &mut Environment,
     context: &LocalContext,
 ) -> Result<Value> {
-    if env.global_context.is_read_only() {
-        return Err(CheckErrors::WriteAttemptedInReadOnly.into());
-    }
+    let writable = env
+        .global_context
+        .check_writable("map-set", &env.contract_context.contract_identifier)?;
 
     check_argument_count(3, args)?;
 
@@ -338,6 +341,10 @@ pub fn special_set_entry(
     env.add_memory(key.get_memory_use())?;
     env.add_memory(value.get_memory_use())?;
 
+    if !writable {
+        return Ok(Value::Bool(true));
+    }
+
     env.global_context
         .database
         .set_entry(contract, map_name, key, value, data_types)
@@ -348,9 +355,9 @@ pub fn special_insert_entry(
     env: &mut Environment,
     context: &LocalContext,
 ) -> Result<Value> {
-    if env.global_context.is_read_only() {
-        return Err(CheckErrors::WriteAttemptedInReadOnly.into());
-    }
+    let writable = env
+        .global_context
+        .check_writable("map-insert", &env.contract_context.contract_identifier)?;
 
     check_argument_count(3, args)?;
 
@@ -377,6 +384,11 @@ pub fn special_insert_entry(
     env.add_memory(key.get_memory_use())?;
     env.add_memory(value.get_memory_use())?;
 
+    // nothing was inserted.
+    if !writable {
+        return Ok(Value::Bool(false));
+    }
+
     env.global_context
         .database
         .insert_entry(contract, map_name, key, value, data_types)
@@ -387,9 +399,9 @@ pub fn special_delete_entry(
     env: &mut Environment,
     context: &LocalContext,
 ) -> Result<Value> {
-    if env.global_context.is_read_only() {
-        return Err(CheckErrors::WriteAttemptedInReadOnly.into());
-    }
+    let writable = env
+        .global_context
+        .check_writable("map-delete", &env.contract_context.contract_identifier)?;
 
     check_argument_count(2, args)?;
 
@@ -413,9 +425,26 @@ pub fn special_delete_entry(
 
     env.add_memory(key.get_memory_use())?;
 
-    env.global_context
+    // nothing was deleted.
+    if !writable {
+        return Ok(Value::Bool(false));
+    }
+
+    let deleted = env
+        .global_context
         .database
-        .delete_entry(contract, map_name, &key, data_types)
+        .delete_entry(contract, map_name, &key, data_types)?;
+
+    // the storage the entry's value occupied is freed.
+    if deleted == Value::Bool(true) {
+        let refund = ExecutionCost {
+            write_length: data_types.value_type.size() as u64,
+            ..ExecutionCost::zero()
+        };
+        env.global_context.add_refund(&refund)?;
+    }
+
+    Ok(deleted)
 }
 
 pub fn special_get_block_info(
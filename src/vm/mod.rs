@@ -58,11 +58,12 @@ pub use vm::types::Value;
 use vm::types::{PrincipalData, QualifiedContractIdentifier, TraitIdentifier, TypeSignature};
 
 pub use vm::representations::{
-    ClarityName, ContractName, SymbolicExpression, SymbolicExpressionType,
+    is_valid_clarity_name, is_valid_contract_name, ClarityName, ContractName, SymbolicExpression,
+    SymbolicExpressionType,
 };
 
 use std::convert::{TryFrom, TryInto};
-pub use vm::contexts::MAX_CONTEXT_DEPTH;
+pub use vm::contexts::{MAX_CONTEXT_DEPTH, MAX_CONTRACT_VARIABLES};
 use vm::costs::cost_functions::ClarityCostFunction;
 pub use vm::functions::stx_transfer_consolidated;
 
@@ -149,7 +150,11 @@ pub fn apply(
     }
 
     if let CallableType::SpecialFunction(_, function) = function {
-        env.call_stack.insert(&identifier, track_recursion);
+        env.call_stack.insert(
+            &identifier,
+            &env.contract_context.contract_identifier.name,
+            track_recursion,
+        );
         let mut resp = function(args, env, context);
         add_stack_trace(&mut resp, env);
         env.call_stack.remove(&identifier, track_recursion)?;
@@ -181,7 +186,11 @@ pub fn apply(
         }
         env.call_stack.decr_apply_depth();
 
-        env.call_stack.insert(&identifier, track_recursion);
+        env.call_stack.insert(
+            &identifier,
+            &env.contract_context.contract_identifier.name,
+            track_recursion,
+        );
         let mut resp = match function {
             CallableType::NativeFunction(_, function, cost_function) => {
                 runtime_cost(*cost_function, env, evaluated_args.len())
@@ -207,10 +216,15 @@ pub fn eval<'a>(
         Atom, AtomValue, Field, List, LiteralValue, TraitReference,
     };
 
+    env.global_context.record_coverage(exp.id);
+
     match exp.expr {
         AtomValue(ref value) | LiteralValue(ref value) => Ok(value.clone()),
         Atom(ref value) => lookup_variable(&value, context, env),
         List(ref children) => {
+            if env.global_context.is_aborted() {
+                return Err(RuntimeErrorType::Aborted.into());
+            }
             let (function_variable, rest) = children
                 .split_first()
                 .ok_or(CheckErrors::NonFunctionApplication)?;
@@ -261,6 +275,9 @@ fn eval_all(
             match try_define {
                 DefineResult::Variable(name, value) => {
                     runtime_cost(ClarityCostFunction::BindName, global_context, 0)?;
+                    if contract_context.variables.len() >= MAX_CONTRACT_VARIABLES {
+                        return Err(RuntimeErrorType::TooManyDefinitions.into());
+                    }
                     let value_memory_use = value.get_memory_use();
                     global_context.add_memory(value_memory_use)?;
                     total_memory_use += value_memory_use;
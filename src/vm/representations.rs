@@ -31,10 +31,7 @@ macro_rules! guarded_string {
         impl TryFrom<String> for $Name {
             type Error = RuntimeErrorType;
             fn try_from(value: String) -> Result<Self, Self::Error> {
-                if value.len() > (MAX_STRING_LEN as usize) {
-                    return Err(RuntimeErrorType::BadNameValue($Label, value));
-                }
-                if $Regex.is_match(&value) {
+                if is_valid_name(&value, &$Regex) {
                     Ok(Self(value))
                 } else {
                     Err(RuntimeErrorType::BadNameValue($Label, value))
@@ -98,6 +95,20 @@ guarded_string!(ClarityName, "ClarityName", CLARITY_NAME_REGEX);
 guarded_string!(ContractName, "ContractName", CONTRACT_NAME_REGEX);
 guarded_string!(UrlString, "UrlString", URL_STRING_REGEX);
 
+fn is_valid_name(value: &str, regex: &Regex) -> bool {
+    value.len() <= (MAX_STRING_LEN as usize) && regex.is_match(value)
+}
+
+/// Whether `value` would be accepted as a `ContractName`, without constructing one.
+pub fn is_valid_contract_name(value: &str) -> bool {
+    is_valid_name(value, &CONTRACT_NAME_REGEX)
+}
+
+/// Whether `value` would be accepted as a `ClarityName`, without constructing one.
+pub fn is_valid_clarity_name(value: &str) -> bool {
+    is_valid_name(value, &CLARITY_NAME_REGEX)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum PreSymbolicExpressionType {
     AtomValue(Value),
@@ -523,3 +534,28 @@ impl Span {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_name_validation() {
+        assert!(is_valid_contract_name("hello-world"));
+        assert!(is_valid_contract_name("__transient"));
+        assert!(!is_valid_contract_name("hello.world"));
+        assert!(!is_valid_contract_name("1hello"));
+        assert!(!is_valid_contract_name(
+            &"a".repeat(MAX_STRING_LEN as usize + 1)
+        ));
+
+        assert!(is_valid_clarity_name("map-get?"));
+        assert!(is_valid_clarity_name("<="));
+        assert!(!is_valid_clarity_name("get balance"));
+        assert!(!is_valid_clarity_name(""));
+
+        // the validators agree with the conversions.
+        assert!(ContractName::try_from("hello.world".to_string()).is_err());
+        assert!(ClarityName::try_from("map-get?".to_string()).is_ok());
+    }
+}
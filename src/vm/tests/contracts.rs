@@ -14,27 +14,39 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+
 use chainstate::burn::BlockHeaderHash;
 use chainstate::stacks::index::storage::TrieFileStorage;
 use chainstate::stacks::index::MarfTrieId;
 use chainstate::stacks::StacksBlockId;
+use chainstate::stacks::TransactionPostConditionMode;
 use util::hash::hex_bytes;
+use vm::analysis;
 use vm::ast;
 use vm::ast::errors::ParseErrors;
+use vm::callables::{DefineType, DefinedFunction};
 use vm::clarity::ClarityInstance;
-use vm::contexts::{Environment, GlobalContext, OwnedEnvironment};
+use vm::contexts::{
+    AssetMap, CallTarget, CommitOutcome, ContractContext, Environment, GlobalContext, LedgerAmount,
+    LocalContext, OwnedEnvironment, OwnedEnvironmentConfig,
+};
 use vm::contracts::Contract;
-use vm::costs::ExecutionCost;
+use vm::costs::{ExecutionCost, LimitedCostTracker};
 use vm::database::{
     ClarityDatabase, MarfedKV, MemoryBackingStore, NULL_BURN_STATE_DB, NULL_HEADER_DB,
 };
-use vm::errors::{CheckErrors, Error, RuntimeErrorType};
+use vm::errors::{CheckErrors, Error, InterpreterError, RuntimeErrorType};
 use vm::execute as vm_execute;
-use vm::representations::SymbolicExpression;
+use vm::representations::{ClarityName, SymbolicExpression};
 use vm::types::{
-    OptionalData, PrincipalData, QualifiedContractIdentifier, ResponseData, StandardPrincipalData,
-    TypeSignature, Value,
+    AssetIdentifier, OptionalData, PrincipalData, QualifiedContractIdentifier, ResponseData,
+    StandardPrincipalData, TypeSignature, Value,
 };
+use vm::{MAX_CALL_STACK_DEPTH, MAX_CONTEXT_DEPTH};
 
 use vm::tests::{execute, symbols_from_values, with_marfed_environment, with_memory_environment};
 
@@ -1068,135 +1080,2078 @@ fn test_at_unknown_block() {
 }
 
 #[test]
-fn test_as_max_len() {
+fn test_no_such_contract() {
     fn test(owned_env: &mut OwnedEnvironment) {
-        let contract = "(define-data-var token-ids (list 10 uint) (list))
-                        (var-set token-ids 
-                           (unwrap! (as-max-len? (append (var-get token-ids) u1) u10) (err 10)))";
+        let contract_identifier = QualifiedContractIdentifier::local("missing").unwrap();
+        let expected: Error = CheckErrors::NoSuchContract(contract_identifier.to_string()).into();
+
+        let err = owned_env
+            .execute_transaction(get_principal(), contract_identifier.clone(), "foo", &[])
+            .unwrap_err();
+        assert_eq!(err, expected);
+
+        let err = owned_env
+            .eval_read_only(&contract_identifier, "(+ 1 2)")
+            .unwrap_err();
+        assert_eq!(err, expected);
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
 
+#[test]
+fn test_eval_read_only_simulated() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-fungible-token tokens)
+                        (ft-mint? tokens u10 (as-contract tx-sender))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
         owned_env
-            .initialize_contract(
-                QualifiedContractIdentifier::local("contract").unwrap(),
-                &contract,
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        let (result, asset_map) = env
+            .eval_read_only_simulated(
+                &contract_identifier,
+                "(as-contract (ft-transfer? tokens u3 tx-sender 'S1G2081040G2081040G2081040G208105NK8PE5))",
             )
             .unwrap();
+        assert_eq!(result, Value::okay_true());
+
+        let asset_identifier = AssetIdentifier {
+            contract_identifier: contract_identifier.clone(),
+            asset_name: "tokens".into(),
+        };
+        let contract_principal = PrincipalData::Contract(contract_identifier.clone());
+        assert_eq!(
+            asset_map.get_fungible_tokens(&contract_principal, &asset_identifier),
+            Some(3)
+        );
+
+        // the transfer itself was rolled back.
+        assert_eq!(
+            env.eval_read_only(
+                &contract_identifier,
+                "(ft-get-balance tokens (as-contract tx-sender))"
+            )
+            .unwrap(),
+            Value::UInt(10)
+        );
     }
 
+    with_memory_environment(test, true);
     with_marfed_environment(test, true);
 }
 
 #[test]
-fn test_ast_stack_depth() {
-    let program = "(+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+fn test_call_stack_frame_removed_on_error() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let function = DefinedFunction::new(
+            vec![],
+            SymbolicExpression::atom_value(Value::Int(1)),
+            DefineType::Private,
+            &"foo".into(),
+            "contract",
+        );
+        let identifier = function.get_identifier();
 
-                      ";
-    assert_eq!(
-        vm_execute(program).unwrap_err(),
-        RuntimeErrorType::ASTError(ParseErrors::ExpressionStackDepthTooDeep.into()).into()
-    );
+        let mut env = owned_env.get_exec_environment(None);
+        let result: Result<(), Error> = env.with_call_stack_frame(&identifier, true, |env| {
+            assert!(env.call_stack.contains(&identifier));
+            Err(RuntimeErrorType::NotImplemented.into())
+        });
+
+        assert_eq!(result, Err(RuntimeErrorType::NotImplemented.into()));
+        assert!(!env.call_stack.contains(&identifier));
+        assert_eq!(env.call_stack.depth(), 0);
+    }
+
+    with_memory_environment(test, true);
 }
 
 #[test]
-fn test_arg_stack_depth() {
-    let program = "(define-private (foo)
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                       bar 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
-                       (define-private (bar)
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
-                       (foo)
-                      ";
-    assert_eq!(
-        vm_execute(program).unwrap_err(),
-        RuntimeErrorType::MaxStackDepthReached.into()
-    );
+fn test_abort_handle() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 0)
+                        (define-public (incr) (ok (var-set counter (+ (var-get counter) 1))))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let abort = owned_env.abort_handle();
+        abort.store(true, Ordering::SeqCst);
+        let err = owned_env
+            .execute_transaction(get_principal(), contract_identifier.clone(), "incr", &[])
+            .unwrap_err();
+        assert_eq!(err, RuntimeErrorType::Aborted.into());
+
+        abort.store(false, Ordering::SeqCst);
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "(var-get counter)")
+            .unwrap();
+        assert_eq!(result, Value::Int(0));
+    }
+
+    with_memory_environment(test, true);
 }
 
 #[test]
-fn test_cc_stack_depth() {
-    let contract_one = "(define-public (foo) 
-                        (ok (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)))";
-    let contract_two =
-                      "(define-private (bar) 
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
-                        (unwrap-panic (contract-call? .c-foo foo ) )
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
-                       (bar)
-                      ";
+fn test_execute_transaction_checked() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-public (check (x int)) (if (> x 0) (ok x) (err (- 0 x))))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
 
-    with_marfed_environment(
-        |owned_env| {
-            let mut env = owned_env.get_exec_environment(None);
+        let (result, _) = owned_env
+            .execute_transaction_checked(
+                get_principal(),
+                contract_identifier.clone(),
+                "check",
+                &symbols_from_values(vec![Value::Int(2)]),
+            )
+            .unwrap();
+        assert_eq!(result, Ok(Value::Int(2)));
+
+        let (result, _) = owned_env
+            .execute_transaction_checked(
+                get_principal(),
+                contract_identifier.clone(),
+                "check",
+                &symbols_from_values(vec![Value::Int(-3)]),
+            )
+            .unwrap();
+        assert_eq!(result, Err(Value::Int(3)));
+    }
 
-            let contract_identifier = QualifiedContractIdentifier::local("c-foo").unwrap();
-            env.initialize_contract(contract_identifier, contract_one)
-                .unwrap();
+    with_memory_environment(test, true);
+}
 
-            let contract_identifier = QualifiedContractIdentifier::local("c-bar").unwrap();
-            assert_eq!(
-                env.initialize_contract(contract_identifier, contract_two)
-                    .unwrap_err(),
-                RuntimeErrorType::MaxStackDepthReached.into()
-            );
-        },
-        false,
-    );
+#[test]
+#[cfg(feature = "developer-mode")]
+fn test_install_contract_context() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract_identifier = QualifiedContractIdentifier::local("fixture").unwrap();
+        let mut contract_context = ContractContext::new(contract_identifier.clone());
+        contract_context.set_variable("answer".into(), Value::Int(42));
+        owned_env
+            .install_contract_context(contract_context.clone())
+            .unwrap();
+
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "(+ answer 1)")
+            .unwrap();
+        assert_eq!(result, Value::Int(43));
+
+        let err = owned_env
+            .install_contract_context(contract_context)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            CheckErrors::ContractAlreadyExists(contract_identifier.to_string()).into()
+        );
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
 }
 
 #[test]
-fn test_cc_trait_stack_depth() {
-    let contract_one = "(define-public (foo)
-                        (ok (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)))";
-    let contract_two =
-                      "(define-trait trait-1 (
-                        (foo () (response int int))))
-                       (define-private (bar (F <trait-1>))
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
-                        (unwrap-panic (contract-call? F foo))
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
-                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
-                       (bar .c-foo)
-                      ";
+fn test_execute_transaction_with_prints() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let callee =
+            "(define-public (noisy (x int)) (begin (print x) (if (> x 0) (ok x) (err x))))";
+        let contract = "(define-public (check (x int))
+                          (begin (print \"checking\")
+                                 (unwrap-err! (contract-call? .callee noisy (- 0 x)) (ok 0))
+                                 (contract-call? .callee noisy x)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(
+                QualifiedContractIdentifier::local("callee").unwrap(),
+                &callee,
+            )
+            .unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
 
-    with_marfed_environment(
-        |owned_env| {
-            let mut env = owned_env.get_exec_environment(None);
+        let (result, _, prints) = owned_env
+            .execute_transaction_with_prints(
+                get_principal(),
+                contract_identifier.clone(),
+                "check",
+                &symbols_from_values(vec![Value::Int(2)]),
+            )
+            .unwrap();
+        assert_eq!(result, Value::okay(Value::Int(2)).unwrap());
+        // the inner call returned an err, so its print was rolled back.
+        assert_eq!(
+            prints,
+            vec![
+                Value::string_ascii_from_bytes("checking".as_bytes().to_vec()).unwrap(),
+                Value::Int(2)
+            ]
+        );
+    }
 
-            let contract_identifier = QualifiedContractIdentifier::local("c-foo").unwrap();
-            env.initialize_contract(contract_identifier, contract_one)
-                .unwrap();
+    with_memory_environment(test, true);
+}
 
-            let contract_identifier = QualifiedContractIdentifier::local("c-bar").unwrap();
+#[test]
+#[cfg(feature = "developer-mode")]
+fn test_read_log() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 0)
+                        (define-map totals { id: int } { total: int })";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        env.global_context.enable_read_log();
+        env.eval_read_only(&contract_identifier, "(var-get counter)")
+            .unwrap();
+        let read_log = env.global_context.take_read_log();
+        assert!(read_log.iter().any(|key| key.ends_with("::counter")));
+        assert!(!read_log.iter().any(|key| key.contains("::totals")));
+
+        assert!(env.global_context.take_read_log().is_empty());
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_depth_remaining() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let function = DefinedFunction::new(
+            vec![],
+            SymbolicExpression::atom_value(Value::Int(1)),
+            DefineType::Private,
+            &"foo".into(),
+            "contract",
+        );
+        let identifier = function.get_identifier();
+
+        let mut env = owned_env.get_exec_environment(None);
+        let context = LocalContext::new();
+        assert_eq!(
+            env.depth_remaining(&context),
+            (MAX_CONTEXT_DEPTH, MAX_CALL_STACK_DEPTH)
+        );
+
+        let inner_context = context.extend().unwrap();
+        env.with_call_stack_frame(&identifier, false, |env| {
             assert_eq!(
-                env.initialize_contract(contract_identifier, contract_two)
-                    .unwrap_err(),
-                RuntimeErrorType::MaxStackDepthReached.into()
+                env.depth_remaining(&inner_context),
+                (MAX_CONTEXT_DEPTH - 1, MAX_CALL_STACK_DEPTH - 1)
             );
-        },
-        false,
-    );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_deploy_and_call() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var initialized bool false)
+                        (define-public (init (ok-p bool))
+                          (begin (var-set initialized true)
+                                 (if ok-p (ok true) (err false))))";
+        let deployer = get_principal();
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+
+        let (result, _) = owned_env
+            .deploy_and_call(
+                deployer.clone(),
+                "contract",
+                contract,
+                "init",
+                &[Value::Bool(false)],
+            )
+            .unwrap();
+        assert_eq!(result, Value::error(Value::Bool(false)).unwrap());
+        let err = owned_env
+            .eval_read_only(&contract_identifier, "(var-get initialized)")
+            .unwrap_err();
+        assert_eq!(
+            err,
+            CheckErrors::NoSuchContract(contract_identifier.to_string()).into()
+        );
+
+        let err = owned_env
+            .deploy_and_call(deployer.clone(), "contract", contract, "missing", &[])
+            .unwrap_err();
+        assert_eq!(err, CheckErrors::UndefinedFunction("missing".into()).into());
+
+        let (result, _) = owned_env
+            .deploy_and_call(
+                deployer.clone(),
+                "contract",
+                contract,
+                "init",
+                &[Value::Bool(true)],
+            )
+            .unwrap();
+        assert_eq!(result, Value::okay_true());
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "(var-get initialized)")
+            .unwrap();
+        assert_eq!(result, Value::Bool(true));
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+#[cfg(feature = "developer-mode")]
+fn test_get_contract_ast() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 0)
+                        (define-read-only (get-counter) (var-get counter))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        let expressions = env.get_contract_ast(&contract_identifier).unwrap();
+        assert_eq!(
+            expressions,
+            ast::build_ast(&contract_identifier, contract, &mut ())
+                .unwrap()
+                .expressions
+        );
+
+        let missing = QualifiedContractIdentifier::local("missing").unwrap();
+        assert_eq!(
+            env.get_contract_ast(&missing).unwrap_err(),
+            CheckErrors::NoSuchContract(missing.to_string()).into()
+        );
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_coverage() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-public (pick (x bool)) (if x (ok 1) (err 2)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let expressions = ast::build_ast(&contract_identifier, contract, &mut ())
+            .unwrap()
+            .expressions;
+        // (define-public (pick (x bool)) (if x (ok 1) (err 2)))
+        let if_expression = &expressions[0].match_list().unwrap()[2];
+        let branches = if_expression.match_list().unwrap();
+        let (ok_branch, err_branch) = (branches[2].id, branches[3].id);
+
+        owned_env.begin();
+        let mut env = owned_env.get_exec_environment(Some(get_principal()));
+        assert!(env.global_context.take_coverage().is_empty());
+        env.global_context.enable_coverage();
+        env.execute_contract(
+            &contract_identifier,
+            "pick",
+            &symbols_from_values(vec![Value::Bool(true)]),
+            false,
+        )
+        .unwrap();
+
+        let coverage = env.global_context.take_coverage();
+        assert!(coverage.contains(&if_expression.id));
+        assert!(coverage.contains(&ok_branch));
+        assert!(!coverage.contains(&err_branch));
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_get_data_var() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 1)
+                        (define-public (incr) (ok (var-set counter (+ (var-get counter) 1))))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+        owned_env
+            .execute_transaction(get_principal(), contract_identifier.clone(), "incr", &[])
+            .unwrap();
+
+        let contract_name = contract_identifier.to_string();
+        let mut env = owned_env.get_exec_environment(None);
+        assert_eq!(
+            env.get_data_var(&contract_name, "counter").unwrap(),
+            Some(Value::Int(2))
+        );
+        assert_eq!(env.get_data_var(&contract_name, "missing").unwrap(), None);
+        assert_eq!(
+            env.get_data_var(
+                &QualifiedContractIdentifier::local("missing")
+                    .unwrap()
+                    .to_string(),
+                "counter"
+            )
+            .unwrap(),
+            None
+        );
+        env.get_data_var("not-qualified", "counter").unwrap_err();
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_contract_metadata() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let with_metadata = QualifiedContractIdentifier::local("with-metadata").unwrap();
+        let without_metadata = QualifiedContractIdentifier::local("without-metadata").unwrap();
+        let mut metadata = HashMap::new();
+        metadata.insert("compiler".to_string(), "clarinet 0.1".to_string());
+        metadata.insert("source-hash".to_string(), "abcd".to_string());
+
+        owned_env
+            .execute_in_env(get_principal(), |env| {
+                env.initialize_contract_with_metadata(
+                    with_metadata.clone(),
+                    "(define-data-var x int 1)",
+                    metadata.clone(),
+                )?;
+                env.initialize_contract(without_metadata.clone(), "(define-data-var x int 1)")
+            })
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        assert_eq!(
+            env.get_contract_metadata(&with_metadata.to_string())
+                .unwrap(),
+            metadata
+        );
+        assert!(env
+            .get_contract_metadata(&without_metadata.to_string())
+            .unwrap()
+            .is_empty());
+
+        let missing = QualifiedContractIdentifier::local("missing").unwrap();
+        assert_eq!(
+            env.get_contract_metadata(&missing.to_string()).unwrap_err(),
+            CheckErrors::NoSuchContract(missing.to_string()).into()
+        );
+        assert_eq!(
+            env.get_contract_source(&without_metadata.to_string())
+                .unwrap(),
+            Some("(define-data-var x int 1)".to_string())
+        );
+        assert_eq!(
+            env.get_contract_source(&missing.to_string()).unwrap_err(),
+            CheckErrors::NoSuchContract(missing.to_string()).into()
+        );
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_get_map_entry() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-map balances principal uint)
+                        (map-set balances tx-sender u10)";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let contract_name = contract_identifier.to_string();
+        let issuer = Value::from(contract_identifier.issuer.clone());
+        let mut env = owned_env.get_exec_environment(None);
+        assert_eq!(
+            env.get_map_entry(&contract_name, "balances", &issuer)
+                .unwrap(),
+            Some(Value::UInt(10))
+        );
+        assert_eq!(
+            env.get_map_entry(&contract_name, "balances", &get_principal())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            env.get_map_entry(&contract_name, "missing", &issuer)
+                .unwrap(),
+            None
+        );
+        // the key must have the map's key type.
+        env.get_map_entry(&contract_name, "balances", &Value::Int(1))
+            .unwrap_err();
+
+        assert_eq!(
+            env.get_map_entries(
+                &contract_name,
+                "balances",
+                &[get_principal(), issuer.clone(), get_principal()]
+            )
+            .unwrap(),
+            vec![None, Some(Value::UInt(10)), None]
+        );
+        assert_eq!(
+            env.get_map_entries(&contract_name, "missing", &[issuer.clone()])
+                .unwrap(),
+            vec![None]
+        );
+        env.get_map_entries(&contract_name, "balances", &[issuer, Value::Int(1)])
+            .unwrap_err();
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_initialize_contracts_in_transaction() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let factory = "(define-data-var children int 1)
+                       (define-read-only (get-children) (var-get children))";
+        let child =
+            "(define-read-only (get-parent-children) (contract-call? .factory get-children))";
+        let factory_identifier = QualifiedContractIdentifier::local("factory").unwrap();
+        let child_identifier = QualifiedContractIdentifier::local("child").unwrap();
+        let sender = Value::from(factory_identifier.issuer.clone());
+
+        // both deployments roll back together...
+        {
+            let mut scope = owned_env.transaction();
+            let mut env = scope.get_exec_environment(Some(sender.clone()));
+            env.initialize_contract(factory_identifier.clone(), factory)
+                .unwrap();
+            env.initialize_contract(child_identifier.clone(), child)
+                .unwrap();
+        }
+        assert!(owned_env
+            .eval_read_only(&child_identifier, "(get-parent-children)")
+            .is_err());
+
+        // ...or persist together.
+        let mut scope = owned_env.transaction();
+        {
+            let mut env = scope.get_exec_environment(Some(sender));
+            env.initialize_contract(factory_identifier.clone(), factory)
+                .unwrap();
+            env.initialize_contract(child_identifier.clone(), child)
+                .unwrap();
+        }
+        scope.commit().unwrap();
+
+        let (result, _, _) = owned_env
+            .eval_read_only(&child_identifier, "(get-parent-children)")
+            .unwrap();
+        assert_eq!(result, Value::Int(1));
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_had_side_effects() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 0)
+                        (define-read-only (get-counter) (var-get counter))
+                        (define-public (peek) (ok (var-get counter)))
+                        (define-public (incr) (ok (var-set counter (+ (var-get counter) 1))))
+                        (define-public (incr-then-fail)
+                          (begin (var-set counter 10) (err u1)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+        assert!(owned_env.had_side_effects());
+
+        for (function, had_side_effects) in [
+            ("get-counter", false),
+            ("peek", false),
+            ("incr", true),
+            ("incr-then-fail", false),
+        ]
+        .iter()
+        {
+            owned_env
+                .execute_transaction(get_principal(), contract_identifier.clone(), function, &[])
+                .unwrap();
+            assert_eq!(
+                owned_env.had_side_effects(),
+                *had_side_effects,
+                "{}",
+                function
+            );
+        }
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_as_contract_caller() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let mut env = owned_env.get_exec_environment(Some(get_principal()));
+        let (caller, sender) = env
+            .as_contract_caller(&"vault".into(), |env| {
+                Ok((env.eval_raw("contract-caller")?, env.eval_raw("tx-sender")?))
+            })
+            .unwrap();
+
+        assert_eq!(
+            caller,
+            Value::from(QualifiedContractIdentifier::local("vault").unwrap())
+        );
+        assert_eq!(sender, get_principal());
+        // the caller is only changed within the closure.
+        assert_eq!(env.eval_raw("contract-caller").unwrap(), get_principal());
+    }
+
+    with_memory_environment(test, false);
+}
+
+#[test]
+fn test_transfer_as_contract() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let tokens = AssetIdentifier {
+            contract_identifier: QualifiedContractIdentifier::local("tokens").unwrap(),
+            asset_name: "tokens".into(),
+        };
+        let recipient = get_principal().expect_principal();
+
+        let (contract, asset_map, events) = owned_env
+            .execute_in_env(get_principal(), |env| {
+                env.transfer_as_contract(&tokens, &recipient, 10)?;
+                env.transfer_as_contract(&tokens, &recipient, 5)?;
+                Ok::<_, Error>(env.contract_principal())
+            })
+            .unwrap();
+
+        assert_eq!(asset_map.get_fungible_tokens(&contract, &tokens), Some(15));
+        assert_eq!(asset_map.get_fungible_tokens(&recipient, &tokens), None);
+        assert_eq!(events.len(), 2);
+    }
+
+    with_memory_environment(test, false);
+}
+
+#[test]
+fn test_caller_chain() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let sender = get_principal();
+        let a = Value::from(QualifiedContractIdentifier::local("a").unwrap());
+        let b = Value::from(QualifiedContractIdentifier::local("b").unwrap());
+
+        let mut env = owned_env.get_exec_environment(Some(sender.clone()));
+        assert_eq!(env.caller_chain(), vec![sender.clone().expect_principal()]);
+
+        let mut called_env = env.nest_with_caller(a.clone());
+        let as_contract_env = called_env.nest_as_principal(b.clone());
+        assert_eq!(
+            as_contract_env.caller_chain(),
+            vec![
+                sender.expect_principal(),
+                a.expect_principal(),
+                b.expect_principal()
+            ]
+        );
+    }
+
+    with_memory_environment(test, false);
+}
+
+#[test]
+fn test_eval_read_only_parse_cache() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 1)
+                        (define-public (incr) (ok (var-set counter (+ (var-get counter) 1))))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        env.global_context.enable_parse_cache(4);
+
+        assert_eq!(
+            env.eval_read_only(&contract_identifier, "(var-get counter)")
+                .unwrap(),
+            Value::Int(1)
+        );
+        // a cached program still sees the latest state.
+        env.execute_contract(&contract_identifier, "incr", &[], false)
+            .unwrap();
+        assert_eq!(
+            env.eval_read_only(&contract_identifier, "(var-get counter)")
+                .unwrap(),
+            Value::Int(2)
+        );
+        assert_eq!(env.eval_raw("(+ 1 2)").unwrap(), Value::Int(3));
+        assert_eq!(env.eval_raw("(+ 1 2)").unwrap(), Value::Int(3));
+    }
+
+    with_memory_environment(test, false);
+}
+
+#[test]
+fn test_commit_db_only() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-fungible-token tokens)
+                        (define-public (mint-and-send)
+                          (begin (try! (ft-mint? tokens u10 tx-sender))
+                                 (ft-transfer? tokens u4 tx-sender 'SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let sender = get_principal();
+        let tokens = AssetIdentifier {
+            contract_identifier: contract_identifier.clone(),
+            asset_name: "tokens".into(),
+        };
+
+        owned_env.begin();
+        {
+            let mut env = owned_env.get_exec_environment(Some(sender.clone()));
+            let scope = env.global_context.begin_scope();
+            env.execute_contract(&contract_identifier, "mint-and-send", &[], false)
+                .unwrap();
+            let discarded = env.global_context.commit_scope_db_only(scope).unwrap();
+            assert_eq!(
+                discarded.get_fungible_tokens(&sender.clone().expect_principal(), &tokens),
+                Some(4)
+            );
+        }
+        let (asset_map, _) = owned_env.commit().unwrap();
+        assert!(asset_map.is_empty());
+
+        // the database writes were kept.
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "(ft-get-supply tokens)")
+            .unwrap();
+        assert_eq!(result, Value::UInt(10));
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_contract_call_observer() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let callee = "(define-public (ping) (ok u1))
+                      (define-public (self-ping) (ping))";
+        let caller = "(define-public (call) (contract-call? .callee ping))";
+        let callee_identifier = QualifiedContractIdentifier::local("callee").unwrap();
+        let caller_identifier = QualifiedContractIdentifier::local("caller").unwrap();
+        owned_env
+            .initialize_contract(callee_identifier.clone(), &callee)
+            .unwrap();
+        owned_env
+            .initialize_contract(caller_identifier.clone(), &caller)
+            .unwrap();
+
+        let edges = Rc::new(RefCell::new(vec![]));
+        let observed = edges.clone();
+
+        owned_env.begin();
+        let mut env = owned_env.get_exec_environment(Some(get_principal()));
+        env.global_context
+            .set_contract_call_observer(move |from, to, function_name| {
+                observed
+                    .borrow_mut()
+                    .push((from.clone(), to.clone(), function_name.to_string()))
+            });
+
+        env.execute_contract(&caller_identifier, "call", &[], false)
+            .unwrap();
+        env.execute_contract(&callee_identifier, "self-ping", &[], false)
+            .unwrap();
+
+        let transient = QualifiedContractIdentifier::transient();
+        assert_eq!(
+            *edges.borrow(),
+            vec![
+                (
+                    transient.clone(),
+                    caller_identifier.clone(),
+                    "call".to_string()
+                ),
+                (
+                    caller_identifier,
+                    callee_identifier.clone(),
+                    "ping".to_string()
+                ),
+                (transient, callee_identifier, "self-ping".to_string()),
+            ]
+        );
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_transaction_scope() {
+    fn initialize_in_scope(
+        owned_env: &mut OwnedEnvironment,
+        contract_identifier: &QualifiedContractIdentifier,
+        commit: bool,
+    ) -> Result<(), Error> {
+        let mut scope = owned_env.transaction();
+        scope
+            .get_exec_environment(Some(get_principal()))
+            .initialize_contract(contract_identifier.clone(), "(define-constant answer 42)")?;
+        if commit {
+            scope.commit()?;
+        }
+        Ok(())
+    }
+
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+
+        // dropped without committing: rolled back.
+        initialize_in_scope(owned_env, &contract_identifier, false).unwrap();
+        assert_eq!(
+            owned_env
+                .eval_read_only(&contract_identifier, "answer")
+                .unwrap_err(),
+            CheckErrors::NoSuchContract(contract_identifier.to_string()).into()
+        );
+
+        initialize_in_scope(owned_env, &contract_identifier, true).unwrap();
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "answer")
+            .unwrap();
+        assert_eq!(result, Value::Int(42));
+
+        // an early return through `?` rolls back too.
+        let err = initialize_in_scope(owned_env, &contract_identifier, true).unwrap_err();
+        assert_eq!(
+            err,
+            CheckErrors::ContractAlreadyExists(contract_identifier.to_string()).into()
+        );
+        // ...leaving the environment back at the top level.
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "answer")
+            .unwrap();
+        assert_eq!(result, Value::Int(42));
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_read_only_function_as_transaction() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 0)
+                        (define-read-only (get-counter) (var-get counter))
+                        (define-read-only (bad-incr) (var-set counter (+ (var-get counter) 1)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let (result, _, _) = owned_env
+            .execute_transaction(
+                get_principal(),
+                contract_identifier.clone(),
+                "get-counter",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(result, Value::Int(0));
+
+        let err = owned_env
+            .execute_transaction(
+                get_principal(),
+                contract_identifier.clone(),
+                "bad-incr",
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err, CheckErrors::WriteAttemptedInReadOnly.into());
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_simulate_writes() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 0)
+                        (define-map entries int int)
+                        (define-read-only (bad-incr)
+                           (begin (var-set counter (+ (var-get counter) 1)) (var-get counter)))
+                        (define-read-only (bad-insert)
+                           (list (map-insert entries 1 1) (map-set entries 2 2)
+                                 (map-delete entries 3) (is-some (map-get? entries 1))))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(Some(get_principal()));
+        env.global_context.simulate_writes = true;
+
+        // the write is skipped, so even the rest of the call doesn't see it.
+        let result = env
+            .execute_contract(&contract_identifier, "bad-incr", &[], false)
+            .unwrap();
+        assert_eq!(result, Value::Int(0));
+        assert_eq!(
+            env.eval_read_only(&contract_identifier, "(var-get counter)")
+                .unwrap(),
+            Value::Int(0)
+        );
+
+        let warnings = env.global_context.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("var-set"));
+        assert!(env.global_context.take_warnings().is_empty());
+
+        // skipped map writes return their no-op values.
+        let result = env
+            .execute_contract(&contract_identifier, "bad-insert", &[], false)
+            .unwrap();
+        assert_eq!(
+            result,
+            Value::list_from(vec![
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Bool(false),
+            ])
+            .unwrap()
+        );
+        assert_eq!(env.global_context.take_warnings().len(), 3);
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_asset_map_too_large() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        let asset_name: ClarityName = "names".into();
+        let sender = get_principal().expect_principal();
+
+        // allow exactly 100 NFT transfers.
+        let mut expected = AssetMap::new();
+        for i in 0..100 {
+            expected.add_asset_transfer(
+                &sender,
+                AssetIdentifier {
+                    contract_identifier: contract_identifier.clone(),
+                    asset_name: asset_name.clone(),
+                },
+                Value::Int(i),
+            );
+        }
+
+        let mut env = owned_env.get_exec_environment(None);
+        let global_context = &mut env.global_context;
+        global_context.max_asset_map_bytes = Some(expected.approximate_bytes());
+
+        global_context.begin();
+        global_context.begin();
+        for i in 0..100 {
+            global_context
+                .log_asset_transfer(&sender, &contract_identifier, &asset_name, Value::Int(i))
+                .unwrap();
+        }
+        global_context.commit().unwrap();
+
+        global_context.begin();
+        global_context
+            .log_asset_transfer(&sender, &contract_identifier, &asset_name, Value::Int(100))
+            .unwrap();
+        let err = global_context.commit().unwrap_err();
+        assert_eq!(err, RuntimeErrorType::AssetMapTooLarge.into());
+
+        match global_context.commit().unwrap() {
+            CommitOutcome::TopLevel(asset_map, _, _) => {
+                assert_eq!(asset_map.approximate_bytes(), expected.approximate_bytes())
+            }
+            CommitOutcome::MergedUpward => panic!("Expected a top-level commit"),
+        }
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_credit_token() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-fungible-token tokens u100)";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let alice = get_principal().expect_principal();
+        let tokens = AssetIdentifier {
+            contract_identifier: contract_identifier.clone(),
+            asset_name: "tokens".into(),
+        };
+        owned_env.credit_token(&alice, &tokens, 60).unwrap();
+        owned_env.credit_token(&alice, &tokens, 30).unwrap();
+
+        let (result, _, _) = owned_env
+            .eval_read_only(
+                &contract_identifier,
+                "(list (ft-get-balance tokens tx-sender) (ft-get-supply tokens))",
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            Value::list_from(vec![Value::UInt(90), Value::UInt(90)]).unwrap()
+        );
+
+        // the token's supply limit still applies.
+        owned_env.credit_token(&alice, &tokens, 20).unwrap_err();
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+#[cfg(feature = "developer-mode")]
+fn test_transfer_heights() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-fungible-token tokens)
+                        (define-non-fungible-token widgets uint)
+                        (define-public (send (to principal))
+                           (begin (try! (ft-transfer? tokens u10 tx-sender to))
+                                  (try! (ft-transfer? tokens u20 tx-sender to))
+                                  (nft-transfer? widgets u1 tx-sender to)))
+                        (ft-mint? tokens u100 tx-sender)
+                        (nft-mint? widgets u1 tx-sender)";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let alice = get_principal();
+        let bob = Value::from(
+            PrincipalData::parse_standard_principal("SM2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQVX8X0G")
+                .unwrap(),
+        );
+        let tokens = AssetIdentifier {
+            contract_identifier: contract_identifier.clone(),
+            asset_name: "tokens".into(),
+        };
+        let widgets = AssetIdentifier {
+            contract_identifier: contract_identifier.clone(),
+            asset_name: "widgets".into(),
+        };
+
+        let height = owned_env
+            .get_exec_environment(None)
+            .global_context
+            .database
+            .get_current_block_height();
+        let (result, asset_map, _) = owned_env
+            .execute_transaction(
+                alice.clone(),
+                contract_identifier,
+                "send",
+                &symbols_from_values(vec![bob]),
+            )
+            .unwrap();
+        assert_eq!(result, Value::okay_true());
+
+        let alice = alice.expect_principal();
+        assert_eq!(
+            asset_map.transfer_heights(&alice, &tokens),
+            &[height, height]
+        );
+        assert_eq!(asset_map.transfer_heights(&alice, &widgets), &[height]);
+        assert!(asset_map
+            .transfer_heights(&alice, &AssetIdentifier::STX())
+            .is_empty());
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_max_call_stack_depth() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-private (inner) (ok u1))
+                        (define-private (middle) (inner))
+                        (define-public (deep) (middle))
+                        (define-public (shallow) (ok u1))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        owned_env
+            .execute_transaction(get_principal(), contract_identifier.clone(), "deep", &[])
+            .unwrap();
+        let deep = owned_env.max_call_stack_depth();
+        assert!(deep >= 3);
+
+        // the mark is reset for each transaction.
+        owned_env
+            .execute_transaction(get_principal(), contract_identifier, "shallow", &[])
+            .unwrap();
+        let shallow = owned_env.max_call_stack_depth();
+        assert!(shallow >= 1);
+        assert_eq!(deep, shallow + 2);
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_execute_transaction_with_balance_delta() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-fungible-token tokens)
+                        (define-public (send (amount uint) (to principal))
+                           (ft-transfer? tokens amount tx-sender to))
+                        (ft-mint? tokens u100 tx-sender)";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let bob = Value::from(
+            PrincipalData::parse_standard_principal("SM2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQVX8X0G")
+                .unwrap(),
+        );
+        let tokens = AssetIdentifier {
+            contract_identifier: contract_identifier.clone(),
+            asset_name: "tokens".into(),
+        };
+
+        let (result, asset_map, _, deltas) = owned_env
+            .execute_transaction_with_balance_delta(
+                get_principal(),
+                contract_identifier,
+                "send",
+                &symbols_from_values(vec![Value::UInt(30), bob]),
+                &[tokens.clone()],
+            )
+            .unwrap();
+        assert_eq!(result, Value::okay_true());
+        assert_eq!(deltas, vec![0, -30]);
+        assert_eq!(
+            asset_map.get_fungible_tokens(&get_principal().expect_principal(), &tokens),
+            Some(30)
+        );
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_pin_contract() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var counter int 1)
+                        (define-public (incr) (ok (var-set counter (+ (var-get counter) 1))))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let contract_name = contract_identifier.to_string();
+        owned_env.pin_contract(&contract_name).unwrap();
+
+        // pinning keeps the code in memory, but the contract's data is still read from
+        //  the database.
+        owned_env
+            .execute_transaction(get_principal(), contract_identifier.clone(), "incr", &[])
+            .unwrap();
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "(var-get counter)")
+            .unwrap();
+        assert_eq!(result, Value::Int(2));
+
+        assert_eq!(owned_env.unpin_contract(&contract_name), Ok(true));
+        assert_eq!(owned_env.unpin_contract(&contract_name), Ok(false));
+
+        let missing = QualifiedContractIdentifier::local("missing").unwrap();
+        assert_eq!(
+            owned_env.pin_contract(&missing.to_string()).unwrap_err(),
+            CheckErrors::NoSuchContract(missing.to_string()).into()
+        );
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_owned_environment_with_config() {
+    let mut store = MemoryBackingStore::new();
+    let mut owned_env = OwnedEnvironment::with_config(
+        store.as_clarity_db(),
+        OwnedEnvironmentConfig {
+            reject_zero_transfers: true,
+            ..OwnedEnvironmentConfig::default()
+        },
+    );
+
+    let tokens = AssetIdentifier {
+        contract_identifier: QualifiedContractIdentifier::local("tokens").unwrap(),
+        asset_name: "tokens".into(),
+    };
+    let recipient = get_principal().expect_principal();
+    let err = owned_env
+        .execute_in_env(get_principal(), |env| {
+            env.transfer_as_contract(&tokens, &recipient, 0)
+        })
+        .unwrap_err();
+    assert_eq!(err, RuntimeErrorType::ZeroTokenTransfer.into());
+}
+
+#[test]
+fn test_lenient_response() {
+    let contract = "(define-data-var x int 1)
+                    (define-public (legacy) (begin (var-set x 2) 5))";
+    let contract_identifier = QualifiedContractIdentifier::local("legacy").unwrap();
+
+    let mut store = MemoryBackingStore::new();
+    let mut owned_env = OwnedEnvironment::new(store.as_clarity_db());
+    owned_env
+        .initialize_contract(contract_identifier.clone(), contract)
+        .unwrap();
+    assert_eq!(
+        owned_env
+            .execute_transaction(get_principal(), contract_identifier.clone(), "legacy", &[])
+            .unwrap_err(),
+        CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::IntType).into()
+    );
+
+    let mut store = MemoryBackingStore::new();
+    let mut owned_env = OwnedEnvironment::with_config(
+        store.as_clarity_db(),
+        OwnedEnvironmentConfig {
+            strict_response: false,
+            ..OwnedEnvironmentConfig::default()
+        },
+    );
+    owned_env
+        .initialize_contract(contract_identifier.clone(), contract)
+        .unwrap();
+    let (result, _, _) = owned_env
+        .execute_transaction(get_principal(), contract_identifier.clone(), "legacy", &[])
+        .unwrap();
+    assert_eq!(result, Value::okay(Value::Int(5)).unwrap());
+    let (x, _, _) = owned_env
+        .eval_read_only(&contract_identifier, "(var-get x)")
+        .unwrap();
+    assert_eq!(x, Value::Int(2));
+}
+
+#[test]
+fn test_transfer_log() {
+    let contract = "(define-fungible-token tokens)
+                    (define-non-fungible-token widgets uint)
+                    (define-public (send (to principal))
+                       (begin (try! (ft-transfer? tokens u10 tx-sender to))
+                              (try! (nft-transfer? widgets u1 tx-sender to))
+                              (ft-transfer? tokens u20 tx-sender to)))
+                    (ft-mint? tokens u100 tx-sender)
+                    (nft-mint? widgets u1 tx-sender)";
+    let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+    let tokens = AssetIdentifier {
+        contract_identifier: contract_identifier.clone(),
+        asset_name: "tokens".into(),
+    };
+    let widgets = AssetIdentifier {
+        contract_identifier: contract_identifier.clone(),
+        asset_name: "widgets".into(),
+    };
+    let bob = Value::from(
+        PrincipalData::parse_standard_principal("SM2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQVX8X0G")
+            .unwrap(),
+    );
+
+    let mut store = MemoryBackingStore::new();
+    let mut owned_env = OwnedEnvironment::new(store.as_clarity_db());
+    owned_env
+        .initialize_contract(contract_identifier.clone(), contract)
+        .unwrap();
+    let (_, asset_map, _) = owned_env
+        .execute_transaction(
+            get_principal(),
+            contract_identifier.clone(),
+            "send",
+            &symbols_from_values(vec![bob.clone()]),
+        )
+        .unwrap();
+    assert_eq!(asset_map.transfer_log(), None);
+
+    let mut store = MemoryBackingStore::new();
+    let mut owned_env = OwnedEnvironment::with_config(
+        store.as_clarity_db(),
+        OwnedEnvironmentConfig {
+            record_transfer_log: true,
+            ..OwnedEnvironmentConfig::default()
+        },
+    );
+    owned_env
+        .initialize_contract(contract_identifier.clone(), contract)
+        .unwrap();
+    let (result, asset_map, _) = owned_env
+        .execute_transaction(
+            get_principal(),
+            contract_identifier.clone(),
+            "send",
+            &symbols_from_values(vec![bob]),
+        )
+        .unwrap();
+    assert_eq!(result, Value::okay_true());
+
+    let alice = get_principal().expect_principal();
+    let log: Vec<_> = asset_map
+        .transfer_log()
+        .unwrap()
+        .iter()
+        .map(|transfer| {
+            assert_eq!(transfer.sender, alice);
+            (
+                transfer.index,
+                transfer.asset.clone(),
+                transfer.amount.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        log,
+        vec![
+            (0, tokens.clone(), LedgerAmount::Amount(10)),
+            (1, widgets, LedgerAmount::Asset(Value::UInt(1))),
+            (2, tokens, LedgerAmount::Amount(20)),
+        ]
+    );
+}
+
+#[test]
+fn test_as_max_len() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-data-var token-ids (list 10 uint) (list))
+                        (var-set token-ids 
+                           (unwrap! (as-max-len? (append (var-get token-ids) u1) u10) (err 10)))";
+
+        owned_env
+            .initialize_contract(
+                QualifiedContractIdentifier::local("contract").unwrap(),
+                &contract,
+            )
+            .unwrap();
+    }
+
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_ast_stack_depth() {
+    let program = "(+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                       (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+
+                      ";
+    assert_eq!(
+        vm_execute(program).unwrap_err(),
+        RuntimeErrorType::ASTError(ParseErrors::ExpressionStackDepthTooDeep.into()).into()
+    );
+}
+
+#[test]
+fn test_arg_stack_depth() {
+    let program = "(define-private (foo)
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                       bar 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
+                       (define-private (bar)
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
+                       (foo)
+                      ";
+    assert_eq!(
+        vm_execute(program).unwrap_err(),
+        RuntimeErrorType::MaxStackDepthReached.into()
+    );
+}
+
+#[test]
+fn test_cc_stack_depth() {
+    let contract_one = "(define-public (foo) 
+                        (ok (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)))";
+    let contract_two =
+                      "(define-private (bar) 
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ 
+                        (unwrap-panic (contract-call? .c-foo foo ) )
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
+                       (bar)
+                      ";
+
+    with_marfed_environment(
+        |owned_env| {
+            let mut env = owned_env.get_exec_environment(None);
+
+            let contract_identifier = QualifiedContractIdentifier::local("c-foo").unwrap();
+            env.initialize_contract(contract_identifier, contract_one)
+                .unwrap();
+
+            let contract_identifier = QualifiedContractIdentifier::local("c-bar").unwrap();
+            assert_eq!(
+                env.initialize_contract(contract_identifier, contract_two)
+                    .unwrap_err(),
+                RuntimeErrorType::MaxStackDepthReached.into()
+            );
+        },
+        false,
+    );
+}
+
+#[test]
+fn test_cc_trait_stack_depth() {
+    let contract_one = "(define-public (foo)
+                        (ok (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                       1 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)))";
+    let contract_two =
+                      "(define-trait trait-1 (
+                        (foo () (response int int))))
+                       (define-private (bar (F <trait-1>))
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                        (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+ (+
+                        (unwrap-panic (contract-call? F foo))
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1)
+                         1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1) 1))
+                       (bar .c-foo)
+                      ";
+
+    with_marfed_environment(
+        |owned_env| {
+            let mut env = owned_env.get_exec_environment(None);
+
+            let contract_identifier = QualifiedContractIdentifier::local("c-foo").unwrap();
+            env.initialize_contract(contract_identifier, contract_one)
+                .unwrap();
+
+            let contract_identifier = QualifiedContractIdentifier::local("c-bar").unwrap();
+            assert_eq!(
+                env.initialize_contract(contract_identifier, contract_two)
+                    .unwrap_err(),
+                RuntimeErrorType::MaxStackDepthReached.into()
+            );
+        },
+        false,
+    );
+}
+
+// Deploys `contract` and stores its analysis, as a node does when it processes the
+//  contract's publish transaction.
+fn deploy_with_analysis(
+    store: &mut MemoryBackingStore,
+    contract_identifier: &QualifiedContractIdentifier,
+    contract: &str,
+) {
+    OwnedEnvironment::new(store.as_clarity_db())
+        .initialize_contract(contract_identifier.clone(), contract)
+        .unwrap();
+    let mut expressions = ast::build_ast(contract_identifier, contract, &mut ())
+        .unwrap()
+        .expressions;
+    analysis::run_analysis(
+        contract_identifier,
+        &mut expressions,
+        &mut store.as_analysis_db(),
+        true,
+        LimitedCostTracker::new_free(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_type_of() {
+    let other_identifier = QualifiedContractIdentifier::local("other").unwrap();
+    let other = "(define-trait value-trait ((get-value () (response uint uint))))
+                 (define-public (get-value) (ok u1))";
+    let contract_identifier = QualifiedContractIdentifier::local("typed").unwrap();
+    let contract = "(use-trait value-trait .other.value-trait)
+         (define-data-var counter int 0)
+         (define-read-only (next) (ok (+ (var-get counter) 1)))
+         (define-public (forward (target <value-trait>)) (contract-call? target get-value))
+         (define-public (fetch) (contract-call? .other get-value))
+         (define-private (type-of-program) true)";
+
+    let mut store = MemoryBackingStore::new();
+    deploy_with_analysis(&mut store, &other_identifier, other);
+    deploy_with_analysis(&mut store, &contract_identifier, contract);
+
+    let mut owned_env = OwnedEnvironment::new(store.as_clarity_db());
+    let mut env = owned_env.get_exec_environment(None);
+    let contract_name = contract_identifier.to_string();
+    assert_eq!(
+        env.type_of(&contract_name, "(var-get counter)").unwrap(),
+        TypeSignature::IntType
+    );
+    assert_eq!(
+        env.type_of(&contract_name, "(next)").unwrap(),
+        TypeSignature::new_response(TypeSignature::IntType, TypeSignature::NoType).unwrap()
+    );
+    assert_eq!(
+        env.type_of(&contract_name, "(type-of-program)").unwrap(),
+        TypeSignature::BoolType
+    );
+    assert_eq!(
+        env.type_of(&contract_name, "(fetch)").unwrap(),
+        TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::NoType).unwrap()
+    );
+    assert_eq!(
+        env.type_of(&contract_name, "(forward .other)").unwrap(),
+        TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::UIntType).unwrap()
+    );
+    assert_eq!(
+        env.type_of(&contract_name, "(contract-call? .other get-value)")
+            .unwrap(),
+        TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::NoType).unwrap()
+    );
+
+    // type-checking does not run the program
+    assert_eq!(
+        env.type_of(&contract_name, "(var-set counter 10)").unwrap(),
+        TypeSignature::BoolType
+    );
+    assert_eq!(
+        env.eval_read_only(&contract_identifier, "(var-get counter)")
+            .unwrap(),
+        Value::Int(0)
+    );
+
+    assert_eq!(
+        env.type_of(&contract_name, "(var-get missing)")
+            .unwrap_err(),
+        CheckErrors::NoSuchDataVariable("missing".to_string()).into()
+    );
+    assert_eq!(
+        env.type_of(&contract_name, "1 2").unwrap_err(),
+        RuntimeErrorType::MultipleExpressions.into()
+    );
+    assert!(env
+        .type_of(&contract_name, "1)) (define-private (injected) 2")
+        .is_err());
+    let missing = QualifiedContractIdentifier::local("missing").unwrap();
+    assert_eq!(
+        env.type_of(&missing.to_string(), "1").unwrap_err(),
+        CheckErrors::NoSuchContract(missing.to_string()).into()
+    );
+}
+
+#[test]
+#[cfg(feature = "developer-mode")]
+fn test_mock_contract_call() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let callee = "(define-public (pay) (if true (ok true) (err u1)))";
+        let caller = "(define-data-var calls int 0)
+                      (define-public (go)
+                        (begin (var-set calls (+ (var-get calls) 1))
+                               (contract-call? .callee pay)))";
+        let callee_identifier = QualifiedContractIdentifier::local("callee").unwrap();
+        let caller_identifier = QualifiedContractIdentifier::local("caller").unwrap();
+        owned_env
+            .initialize_contract(callee_identifier.clone(), callee)
+            .unwrap();
+        owned_env
+            .initialize_contract(caller_identifier.clone(), caller)
+            .unwrap();
+
+        let callee_name = callee_identifier.to_string();
+        let failure = Value::error(Value::UInt(7)).unwrap();
+        owned_env
+            .mock_contract_call(&callee_name, "pay", failure.clone())
+            .unwrap();
+
+        // the mocked err response rolls back the caller's write.
+        let (result, _, _) = owned_env
+            .execute_transaction(get_principal(), caller_identifier.clone(), "go", &[])
+            .unwrap();
+        assert_eq!(result, failure);
+        let (calls, _, _) = owned_env
+            .eval_read_only(&caller_identifier, "(var-get calls)")
+            .unwrap();
+        assert_eq!(calls, Value::Int(0));
+
+        assert_eq!(
+            owned_env.unmock_contract_call(&callee_name, "pay"),
+            Ok(true)
+        );
+        assert_eq!(
+            owned_env.unmock_contract_call(&callee_name, "pay"),
+            Ok(false)
+        );
+
+        let (result, _, _) = owned_env
+            .execute_transaction(get_principal(), caller_identifier.clone(), "go", &[])
+            .unwrap();
+        assert_eq!(result, Value::okay(Value::Bool(true)).unwrap());
+
+        assert_eq!(
+            owned_env
+                .mock_contract_call(&callee_name, "pay", Value::Int(1))
+                .unwrap_err(),
+            CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::IntType).into()
+        );
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+fn test_context_scopes() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let mut env = owned_env.get_exec_environment(None);
+        let global_context = &mut env.global_context;
+
+        let outer = global_context.begin_scope();
+        let inner = global_context.begin_read_only_scope();
+        assert!(global_context.is_read_only());
+        global_context.roll_back_scope(inner).unwrap();
+        assert!(!global_context.is_read_only());
+        match global_context.commit_scope(outer).unwrap() {
+            CommitOutcome::TopLevel(..) => {}
+            CommitOutcome::MergedUpward => panic!("Expected the outer scope to be top-level"),
+        }
+        assert!(global_context.is_top_level());
+
+        // finishing a scope while a context nested in it is still open fails, and
+        //  leaves both open.
+        let outer = global_context.begin_scope();
+        global_context.begin();
+        assert_eq!(
+            global_context.commit_scope(outer).unwrap_err(),
+            InterpreterError::MismatchedContextScope.into()
+        );
+        global_context.roll_back();
+        global_context.roll_back();
+        assert!(global_context.is_top_level());
+
+        // a scope whose context was finished some other way can't finish a later context
+        //  opened at the same depth.
+        let stale = global_context.begin_scope();
+        global_context.roll_back();
+        let fresh = global_context.begin_scope();
+        assert_eq!(
+            global_context.roll_back_scope(stale).unwrap_err(),
+            InterpreterError::MismatchedContextScope.into()
+        );
+        global_context.roll_back_scope(fresh).unwrap();
+        assert!(global_context.is_top_level());
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_eval_raw_multiple_expressions() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let mut env = owned_env.get_exec_environment(None);
+        assert_eq!(env.eval_raw("(+ 1 2)\n").unwrap(), Value::Int(3));
+        assert_eq!(
+            env.eval_raw("(+ 1 2) (+ 3 4)").unwrap_err(),
+            RuntimeErrorType::MultipleExpressions.into()
+        );
+    }
+
+    with_memory_environment(test, false);
+}
+
+#[test]
+fn test_contract_principal_string() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let env = owned_env.get_exec_environment(None);
+        assert_eq!(
+            env.contract_principal_string(&"token".into()),
+            QualifiedContractIdentifier::local("token")
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    with_memory_environment(test, false);
+}
+
+#[test]
+fn test_simulate_transaction() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-fungible-token tokens)
+                        (define-public (mint-and-send)
+                          (begin (try! (ft-mint? tokens u10 tx-sender))
+                                 (ft-transfer? tokens u4 tx-sender 'SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let sender = get_principal();
+        let tokens = AssetIdentifier {
+            contract_identifier: contract_identifier.clone(),
+            asset_name: "tokens".into(),
+        };
+
+        let simulation = owned_env
+            .simulate_transaction(
+                sender.clone(),
+                contract_identifier.clone(),
+                "mint-and-send",
+                &[],
+                &TransactionPostConditionMode::Allow,
+                &[],
+            )
+            .unwrap();
+        assert_eq!(simulation.value, Value::okay(Value::Bool(true)).unwrap());
+        assert_eq!(
+            simulation
+                .asset_map
+                .get_fungible_tokens(&sender.clone().expect_principal(), &tokens),
+            Some(4)
+        );
+        assert_eq!(simulation.events.len(), 2);
+        assert!(simulation.post_conditions_passed);
+
+        // the transfer isn't covered by a post-condition.
+        let simulation = owned_env
+            .simulate_transaction(
+                sender.clone(),
+                contract_identifier.clone(),
+                "mint-and-send",
+                &[],
+                &TransactionPostConditionMode::Deny,
+                &[],
+            )
+            .unwrap();
+        assert!(!simulation.post_conditions_passed);
+
+        // nothing was persisted.
+        let (result, _, _) = owned_env
+            .eval_read_only(&contract_identifier, "(ft-get-supply tokens)")
+            .unwrap();
+        assert_eq!(result, Value::UInt(0));
+    }
+
+    with_memory_environment(test, true);
+    with_marfed_environment(test, true);
+}
+
+#[test]
+#[cfg(feature = "developer-mode")]
+fn test_breakpoints() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let callee = "(define-data-var hits int 0)
+                      (define-public (hit) (ok (var-set hits (+ (var-get hits) 1))))";
+        let caller = "(define-public (go) (contract-call? .callee hit))";
+        let callee_identifier = QualifiedContractIdentifier::local("callee").unwrap();
+        let caller_identifier = QualifiedContractIdentifier::local("caller").unwrap();
+        owned_env
+            .initialize_contract(callee_identifier.clone(), callee)
+            .unwrap();
+        owned_env
+            .initialize_contract(caller_identifier.clone(), caller)
+            .unwrap();
+
+        let paused_in = Rc::new(RefCell::new(vec![]));
+        let mut env = owned_env.get_exec_environment(Some(get_principal()));
+        let hit = env
+            .global_context
+            .database
+            .get_contract(&callee_identifier)
+            .unwrap()
+            .contract_context
+            .lookup_function("hit")
+            .unwrap()
+            .get_identifier();
+        env.global_context.set_breakpoint(hit.clone());
+        let paused_in_ref = paused_in.clone();
+        env.global_context
+            .set_debug_callback(move |env: &Environment| {
+                paused_in_ref.borrow_mut().push((
+                    env.contract_context.contract_identifier.clone(),
+                    env.caller_chain(),
+                ));
+            });
+
+        env.execute_contract(&caller_identifier, "go", &[], false)
+            .unwrap();
+        assert_eq!(
+            *paused_in.borrow(),
+            vec![(
+                callee_identifier.clone(),
+                vec![
+                    get_principal().expect_principal(),
+                    PrincipalData::from(caller_identifier.clone())
+                ]
+            )]
+        );
+
+        assert!(env.global_context.clear_breakpoint(&hit));
+        assert!(!env.global_context.clear_breakpoint(&hit));
+        env.execute_contract(&caller_identifier, "go", &[], false)
+            .unwrap();
+        assert_eq!(paused_in.borrow().len(), 1);
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_clone_contract_context_with_name() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-constant factor 3)
+                        (define-read-only (scale (x int)) (* x factor))";
+        let contract_identifier = QualifiedContractIdentifier::local("template").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(Some(get_principal()));
+        let template = env
+            .global_context
+            .database
+            .get_contract(&contract_identifier)
+            .unwrap()
+            .contract_context;
+        let copy = template.clone_with_name("copy".into());
+
+        assert_eq!(
+            copy.contract_identifier,
+            QualifiedContractIdentifier::local("copy").unwrap()
+        );
+        assert_eq!(copy.lookup_variable("factor"), Some(&Value::Int(3)));
+        let scale = copy.lookup_function("scale").unwrap();
+        assert_eq!(
+            scale.get_identifier().to_string(),
+            format!("{}:scale", copy.contract_identifier)
+        );
+        assert_eq!(
+            env.execute_function_as_transaction(&scale, &[Value::Int(2)], Some(&copy))
+                .unwrap(),
+            Value::Int(6)
+        );
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_call_graph() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-trait callee-trait ((pay () (response bool uint))))
+                        (define-private (helper) (ok true))
+                        (define-private (unused) (helper))
+                        (define-public (go)
+                          (begin (try! (helper))
+                                 (try! (helper))
+                                 (contract-call? .other pay)))
+                        (define-public (dispatch (target <callee-trait>))
+                          (contract-call? target pay))";
+        let contract_identifier = QualifiedContractIdentifier::local("caller").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        let call_graph = env
+            .global_context
+            .database
+            .get_contract(&contract_identifier)
+            .unwrap()
+            .contract_context
+            .call_graph();
+
+        assert_eq!(call_graph.len(), 4);
+        assert_eq!(call_graph["helper"], vec![]);
+        assert_eq!(
+            call_graph["unused"],
+            vec![CallTarget::Internal("helper".into())]
+        );
+        assert_eq!(
+            call_graph["go"],
+            vec![
+                CallTarget::Internal("helper".into()),
+                CallTarget::Contract(
+                    QualifiedContractIdentifier::local("other").unwrap(),
+                    "pay".into()
+                )
+            ]
+        );
+        assert_eq!(
+            call_graph["dispatch"],
+            vec![CallTarget::Dynamic("pay".into())]
+        );
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_call_graph_higher_order() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-private (double (x int)) (* 2 x))
+                        (define-private (positive (x int)) (> x 0))
+                        (define-private (sum (x int) (acc int)) (+ x acc))
+                        (define-read-only (doubled) (map double (list 1 2)))
+                        (define-read-only (positives) (filter positive (list -1 2)))
+                        (define-read-only (total) (fold sum (list 1 2) 0))
+                        (define-read-only (added) (map + (list 1) (list 2)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        let call_graph = env
+            .global_context
+            .database
+            .get_contract(&contract_identifier)
+            .unwrap()
+            .contract_context
+            .call_graph();
+
+        assert_eq!(
+            call_graph["doubled"],
+            vec![CallTarget::Internal("double".into())]
+        );
+        assert_eq!(
+            call_graph["positives"],
+            vec![CallTarget::Internal("positive".into())]
+        );
+        assert_eq!(
+            call_graph["total"],
+            vec![CallTarget::Internal("sum".into())]
+        );
+        // native functions passed by name aren't calls into the contract.
+        assert_eq!(call_graph["added"], vec![]);
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_unreachable_functions() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract = "(define-private (helper) true)
+                        (define-private (nested) (helper))
+                        (define-private (dead-end) (helper))
+                        (define-private (orphan) (dead-end))
+                        (define-private (accumulate (x int) (acc int)) (+ x acc))
+                        (define-read-only (total) (fold accumulate (list 1 2) 0))
+                        (define-read-only (peek) (nested))
+                        (define-public (go) (ok (helper)))";
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(contract_identifier.clone(), &contract)
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        let contract_context = env
+            .global_context
+            .database
+            .get_contract(&contract_identifier)
+            .unwrap()
+            .contract_context;
+        let unreachable: Vec<&str> = contract_context
+            .unreachable_functions()
+            .into_iter()
+            .map(|name| name.as_str())
+            .collect();
+        assert_eq!(unreachable, vec!["dead-end", "orphan"]);
+    }
+
+    with_memory_environment(test, true);
+}
+
+#[test]
+fn test_validate_asset_map_contracts() {
+    fn test(owned_env: &mut OwnedEnvironment) {
+        let contract_identifier = QualifiedContractIdentifier::local("contract").unwrap();
+        owned_env
+            .initialize_contract(
+                contract_identifier.clone(),
+                "(define-fungible-token tokens)",
+            )
+            .unwrap();
+
+        let sender = get_principal().expect_principal();
+        let mut asset_map = AssetMap::new();
+        asset_map.add_stx_transfer(&sender, 10).unwrap();
+        asset_map
+            .add_token_transfer(
+                &sender,
+                AssetIdentifier {
+                    contract_identifier: contract_identifier.clone(),
+                    asset_name: "tokens".into(),
+                },
+                5,
+            )
+            .unwrap();
+
+        let mut env = owned_env.get_exec_environment(None);
+        env.validate_asset_map_contracts(&AssetMap::new()).unwrap();
+        env.validate_asset_map_contracts(&asset_map).unwrap();
+
+        let dangling = QualifiedContractIdentifier::local("tokenz").unwrap();
+        asset_map.add_asset_transfer(
+            &sender,
+            AssetIdentifier {
+                contract_identifier: dangling.clone(),
+                asset_name: "widgets".into(),
+            },
+            Value::UInt(1),
+        );
+        assert_eq!(
+            env.validate_asset_map_contracts(&asset_map).unwrap_err(),
+            CheckErrors::NoSuchContract(dangling.to_string()).into()
+        );
+    }
+
+    with_memory_environment(test, true);
 }
 
 #[test]
@@ -247,6 +247,243 @@ fn test_all() {
     }
 }
 
+#[test]
+fn test_execute_transaction_with_cost() {
+    let contract = "(define-data-var counter int 0)
+                    (define-public (bump)
+                      (begin (var-set counter (+ (var-get counter) 1))
+                             (ok (var-get counter))))";
+    let contract_identifier = QualifiedContractIdentifier::local("counter").unwrap();
+
+    let marf_kv = MarfedKV::temporary();
+    let mut clarity_instance = ClarityInstance::new(false, marf_kv, ExecutionCost::max_value());
+    clarity_instance
+        .begin_test_genesis_block(
+            &StacksBlockId::sentinel(),
+            &StacksBlockHeader::make_index_block_hash(
+                &FIRST_BURNCHAIN_CONSENSUS_HASH,
+                &FIRST_STACKS_BLOCK_HASH,
+            ),
+            &NULL_HEADER_DB,
+            &NULL_BURN_STATE_DB,
+        )
+        .commit_block();
+
+    let mut marf_kv = clarity_instance.destroy();
+    let mut store = marf_kv.begin(
+        &StacksBlockHeader::make_index_block_hash(
+            &FIRST_BURNCHAIN_CONSENSUS_HASH,
+            &FIRST_STACKS_BLOCK_HASH,
+        ),
+        &StacksBlockId([1 as u8; 32]),
+    );
+
+    let mut owned_env =
+        OwnedEnvironment::new_max_limit(store.as_clarity_db(&NULL_HEADER_DB, &NULL_BURN_STATE_DB));
+    owned_env
+        .initialize_contract(contract_identifier.clone(), contract)
+        .unwrap();
+
+    let sender = execute("'SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR");
+    let cost_before = owned_env
+        .get_exec_environment(None)
+        .global_context
+        .cost_track
+        .get_total();
+    let (result, _, _, tx_cost) = owned_env
+        .execute_transaction_with_cost(sender, contract_identifier, "bump", &[])
+        .unwrap();
+    assert_eq!(result, Value::okay(Value::Int(1)).unwrap());
+
+    let mut expected_cost = owned_env
+        .get_exec_environment(None)
+        .global_context
+        .cost_track
+        .get_total();
+    expected_cost.sub(&cost_before).unwrap();
+    assert_eq!(tx_cost, expected_cost);
+    assert!(tx_cost.write_count > 0);
+    assert!(tx_cost.read_count > 0);
+}
+
+#[test]
+fn test_cost_refunds() {
+    let cost = ExecutionCost {
+        runtime: 100,
+        write_length: 10,
+        write_count: 2,
+        read_length: 0,
+        read_count: 7,
+    };
+    let refund = ExecutionCost {
+        runtime: 20,
+        write_length: 10,
+        write_count: 1,
+        read_length: 5,
+        read_count: 0,
+    };
+
+    // refunds are capped at half of the cost in each dimension
+    assert_eq!(
+        cost.with_refund(&refund),
+        ExecutionCost {
+            runtime: 80,
+            write_length: 5,
+            write_count: 1,
+            read_length: 0,
+            read_count: 7,
+        }
+    );
+    assert_eq!(cost.with_refund(&ExecutionCost::zero()), cost);
+}
+
+#[test]
+fn test_effective_cost_refunds_freed_storage() {
+    let store_contract = "(define-map entries { k: int } { v: (buff 64) })
+                          (define-public (put (k int))
+                            (ok (map-set entries { k: k } { v: 0x0102030405060708 })))
+                          (define-public (remove (k int))
+                            (ok (map-delete entries { k: k })))
+                          (define-public (remove-and-fail (k int))
+                            (begin (map-delete entries { k: k }) (err u1)))";
+    let caller_contract = "(define-public (call-remove-and-fail)
+                             (begin (unwrap-err! (contract-call? .store remove-and-fail 1) (err u0))
+                                    (ok true)))";
+    let store_id = QualifiedContractIdentifier::local("store").unwrap();
+    let caller_id = QualifiedContractIdentifier::local("caller").unwrap();
+
+    let marf_kv = MarfedKV::temporary();
+    let mut clarity_instance = ClarityInstance::new(false, marf_kv, ExecutionCost::max_value());
+    clarity_instance
+        .begin_test_genesis_block(
+            &StacksBlockId::sentinel(),
+            &StacksBlockHeader::make_index_block_hash(
+                &FIRST_BURNCHAIN_CONSENSUS_HASH,
+                &FIRST_STACKS_BLOCK_HASH,
+            ),
+            &NULL_HEADER_DB,
+            &NULL_BURN_STATE_DB,
+        )
+        .commit_block();
+
+    let mut marf_kv = clarity_instance.destroy();
+    let mut store = marf_kv.begin(
+        &StacksBlockHeader::make_index_block_hash(
+            &FIRST_BURNCHAIN_CONSENSUS_HASH,
+            &FIRST_STACKS_BLOCK_HASH,
+        ),
+        &StacksBlockId([1 as u8; 32]),
+    );
+
+    let mut owned_env =
+        OwnedEnvironment::new_max_limit(store.as_clarity_db(&NULL_HEADER_DB, &NULL_BURN_STATE_DB));
+    owned_env
+        .initialize_contract(store_id.clone(), store_contract)
+        .unwrap();
+    owned_env
+        .initialize_contract(caller_id.clone(), caller_contract)
+        .unwrap();
+
+    let sender = execute("'SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR");
+    let mut run = |contract_id: &QualifiedContractIdentifier, tx_name: &str, args: &[Value]| {
+        let cost_before = owned_env
+            .get_exec_environment(None)
+            .global_context
+            .cost_track
+            .get_total();
+        let (result, _, _, effective_cost) = owned_env
+            .execute_transaction_with_effective_cost(
+                sender.clone(),
+                contract_id.clone(),
+                tx_name,
+                &symbols_from_values(args.to_vec()),
+            )
+            .unwrap();
+        let mut cost = owned_env
+            .get_exec_environment(None)
+            .global_context
+            .cost_track
+            .get_total();
+        cost.sub(&cost_before).unwrap();
+        (result, cost, effective_cost)
+    };
+
+    // nothing is freed by a write.
+    let (_, cost, effective_cost) = run(&store_id, "put", &[Value::Int(1)]);
+    assert_eq!(effective_cost, cost);
+
+    // a delete rolled back along with the call that made it refunds nothing.
+    let (result, cost, effective_cost) = run(&caller_id, "call-remove-and-fail", &[]);
+    assert_eq!(result, Value::okay_true());
+    assert_eq!(effective_cost, cost);
+
+    // deleting the entry refunds (some of) the write length, and only that.
+    let (result, cost, effective_cost) = run(&store_id, "remove", &[Value::Int(1)]);
+    assert_eq!(result, Value::okay_true());
+    assert!(effective_cost.write_length < cost.write_length);
+    assert!(effective_cost.write_length >= cost.write_length / 2);
+    assert_eq!(
+        effective_cost,
+        ExecutionCost {
+            write_length: effective_cost.write_length,
+            ..cost.clone()
+        }
+    );
+
+    // the entry is already gone, so deleting it again frees nothing.
+    let (result, cost, effective_cost) = run(&store_id, "remove", &[Value::Int(1)]);
+    assert_eq!(result, Value::okay(Value::Bool(false)).unwrap());
+    assert_eq!(effective_cost, cost);
+}
+
+#[test]
+fn test_budget_remaining() {
+    let marf_kv = MarfedKV::temporary();
+    let mut clarity_instance = ClarityInstance::new(false, marf_kv, ExecutionCost::max_value());
+    clarity_instance
+        .begin_test_genesis_block(
+            &StacksBlockId::sentinel(),
+            &StacksBlockHeader::make_index_block_hash(
+                &FIRST_BURNCHAIN_CONSENSUS_HASH,
+                &FIRST_STACKS_BLOCK_HASH,
+            ),
+            &NULL_HEADER_DB,
+            &NULL_BURN_STATE_DB,
+        )
+        .commit_block();
+
+    let mut marf_kv = clarity_instance.destroy();
+    let mut store = marf_kv.begin(
+        &StacksBlockHeader::make_index_block_hash(
+            &FIRST_BURNCHAIN_CONSENSUS_HASH,
+            &FIRST_STACKS_BLOCK_HASH,
+        ),
+        &StacksBlockId([1 as u8; 32]),
+    );
+
+    let limit = ExecutionCost {
+        runtime: 1_000_000,
+        write_length: 1000,
+        write_count: 10,
+        read_length: 1000,
+        read_count: 10,
+    };
+    let mut db = store.as_clarity_db(&NULL_HEADER_DB, &NULL_BURN_STATE_DB);
+    let cost_tracker = LimitedCostTracker::new(false, limit.clone(), &mut db).unwrap();
+    let mut owned_env = OwnedEnvironment::new_cost_limited(false, db, cost_tracker);
+
+    let mut env = owned_env.get_exec_environment(None);
+    assert_eq!(env.budget_remaining(), Some(limit.clone()));
+    env.eval_raw("(+ 1 2)").unwrap();
+    let remaining = env.budget_remaining().unwrap();
+    assert!(remaining.runtime < limit.runtime);
+    assert_eq!(remaining.write_count, limit.write_count);
+
+    let mut free_store = MemoryBackingStore::new();
+    let mut free_env = OwnedEnvironment::new_free(false, free_store.as_clarity_db());
+    assert_eq!(free_env.get_exec_environment(None).budget_remaining(), None);
+}
+
 #[test]
 fn test_cost_contract_short_circuits() {
     let marf_kv = MarfedKV::temporary();
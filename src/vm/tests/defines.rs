@@ -16,6 +16,10 @@
 
 use vm::ast::build_ast;
 use vm::ast::errors::ParseErrors;
+use vm::contexts::{GlobalContext, MAX_CONTRACT_VARIABLES};
+use vm::contracts::Contract;
+use vm::costs::LimitedCostTracker;
+use vm::database::MemoryBackingStore;
 use vm::errors::{CheckErrors, Error, RuntimeErrorType};
 use vm::execute;
 use vm::types::{QualifiedContractIdentifier, TypeSignature, Value};
@@ -47,6 +51,32 @@ fn test_defines() {
     assert_eq!(Ok(Some(Value::Int(1))), execute(&tests));
 }
 
+#[test]
+fn test_too_many_constants() {
+    let contract_id = QualifiedContractIdentifier::transient();
+    let at_cap: Vec<String> = (0..MAX_CONTRACT_VARIABLES)
+        .map(|i| format!("(define-constant c{} {})", i, i))
+        .collect();
+    let at_cap = at_cap.join(" ");
+    let past_cap = format!("{} (define-constant one-too-many 0)", at_cap);
+
+    let mut marf = MemoryBackingStore::new();
+    let mut global_context =
+        GlobalContext::new(false, marf.as_clarity_db(), LimitedCostTracker::new_free());
+
+    let parsed = build_ast(&contract_id, &past_cap, &mut ()).unwrap();
+    let err = global_context
+        .execute(|g| Contract::initialize_from_ast(contract_id.clone(), &parsed, g))
+        .err()
+        .expect("Contract initialization should fail past the variable cap");
+    assert_eq!(err, RuntimeErrorType::TooManyDefinitions.into());
+
+    let parsed = build_ast(&contract_id, &at_cap, &mut ()).unwrap();
+    assert!(global_context
+        .execute(|g| Contract::initialize_from_ast(contract_id.clone(), &parsed, g))
+        .is_ok());
+}
+
 #[test]
 fn test_accept_options() {
     let defun = "(define-private (f (b (optional int))) (* 10 (default-to 0 b)))";
@@ -956,6 +956,14 @@ impl Value {
             panic!();
         }
     }
+
+    /// If this is a response, whether it's an `(ok ..)` (i.e., committed), and its inner value.
+    pub fn response_inner(&self) -> Option<(bool, &Value)> {
+        match self {
+            Value::Response(res_data) => Some((res_data.committed, &res_data.data)),
+            _ => None,
+        }
+    }
 }
 
 impl BuffData {
@@ -1086,6 +1094,28 @@ impl PrincipalData {
         }
     }
 
+    /// Parse a c32-encoded address into a standard (non-contract) principal.
+    ///  Contract principals are rejected, since callers of this function expect
+    ///  a user address (e.g., a fee payer or tx-sender).
+    pub fn parse_standard(addr: &str) -> Result<PrincipalData> {
+        // be permissive about leading single-quote
+        let addr = if addr.starts_with("'") {
+            &addr[1..]
+        } else {
+            addr
+        };
+
+        if addr.contains(".") {
+            return Err(RuntimeErrorType::ParseError(format!(
+                "Invalid standard principal literal: {} is a contract principal",
+                addr
+            ))
+            .into());
+        }
+
+        PrincipalData::parse_standard_principal(addr).map(PrincipalData::from)
+    }
+
     pub fn parse_qualified_contract_principal(literal: &str) -> Result<PrincipalData> {
         let contract_id = QualifiedContractIdentifier::parse(literal)?;
         Ok(PrincipalData::Contract(contract_id))
@@ -1439,6 +1469,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_standard() {
+        let addr = "SZ2J6ZY48GV1EZ5V2V5RB9MP66SW86PYKKQ9H6DPR";
+        let expected = PrincipalData::from(PrincipalData::parse_standard_principal(addr).unwrap());
+
+        assert_eq!(PrincipalData::parse_standard(addr).unwrap(), expected);
+        assert_eq!(
+            PrincipalData::parse_standard(&format!("'{}", addr)).unwrap(),
+            expected
+        );
+        assert!(PrincipalData::parse_standard(&format!("{}.contract", addr)).is_err());
+        assert!(PrincipalData::parse_standard("not-an-address").is_err());
+    }
+
+    #[test]
+    fn test_response_inner() {
+        assert_eq!(
+            Value::okay(Value::Int(1)).unwrap().response_inner(),
+            Some((true, &Value::Int(1)))
+        );
+        assert_eq!(
+            Value::error(Value::UInt(2)).unwrap().response_inner(),
+            Some((false, &Value::UInt(2)))
+        );
+        assert_eq!(Value::Int(1).response_inner(), None);
+    }
+
     #[test]
     #[should_panic]
     fn expect_buff_too_small() {
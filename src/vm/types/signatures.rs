@@ -60,6 +60,16 @@ impl AssetIdentifier {
             asset_name: ClarityName::try_from("BURNED".to_string()).unwrap(),
         }
     }
+
+    /// A hash of this identifier's canonical `contract.asset` string which, unlike the
+    ///  derived `Hash`, is stable across runs and platforms.
+    pub fn stable_hash(&self) -> u64 {
+        let canonical = format!("{}.{}", self.contract_identifier, self.asset_name);
+        let digest = hash::Sha512Trunc256Sum::from_data(canonical.as_bytes());
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest.as_bytes()[0..8]);
+        u64::from_be_bytes(bytes)
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -1420,4 +1430,30 @@ mod test {
             TypeSignature::from(*desc); // panics on failed types.
         }
     }
+
+    #[test]
+    fn test_asset_identifier_stable_hash() {
+        let tokens = AssetIdentifier {
+            contract_identifier: QualifiedContractIdentifier::local("contract").unwrap(),
+            asset_name: "tokens".into(),
+        };
+        let other = AssetIdentifier {
+            contract_identifier: QualifiedContractIdentifier::local("contract").unwrap(),
+            asset_name: "names".into(),
+        };
+
+        assert_eq!(tokens.stable_hash(), tokens.clone().stable_hash());
+        assert_ne!(tokens.stable_hash(), other.stable_hash());
+        assert_ne!(
+            AssetIdentifier::STX().stable_hash(),
+            AssetIdentifier::STX_burned().stable_hash()
+        );
+
+        let digest = hash::Sha512Trunc256Sum::from_data(
+            "S1G2081040G2081040G2081040G208105NK8PE5.contract.tokens".as_bytes(),
+        );
+        let mut expected = [0u8; 8];
+        expected.copy_from_slice(&digest.as_bytes()[0..8]);
+        assert_eq!(tokens.stable_hash(), u64::from_be_bytes(expected));
+    }
 }